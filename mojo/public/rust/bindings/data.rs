@@ -9,6 +9,15 @@
 //! messages.
 //!
 //! Some are helpers used in generated code.
+//!
+//! [`Validator`], [`DataValidationError`], [`check_fixed_array_len`],
+//! [`reconstruct_map`], [`StructTraits`], and [`NativeCodec`] are validation
+//! and typemap-bridge machinery that mirrors what a real decode path needs,
+//! but none of it is wired into a codec: `mojom.rs`'s `Decoder`/`Encoder`/
+//! `Context` types live in `decoding.rs`/`encoding.rs`, neither of which
+//! exists in this checkout (and `mojom.rs` itself isn't declared as a module
+//! in `bindings/lib.rs` for the same reason). Treat this as a standalone
+//! sketch of the shape that integration would take, not as live validation.
 
 /// A relative pointer in a mojom serialized message.
 ///
@@ -87,3 +96,273 @@ impl<T: ?Sized> Clone for Pointer<T> {
 
 pub const UNION_DATA_SIZE: usize = 16;
 pub const UNION_INNER_SIZE: usize = 8;
+
+/// Rounds `n` up to the next multiple of 8, Mojom's alignment for pointers
+/// and array elements.
+fn round_up_8(n: usize) -> usize {
+    (n + 7) & !7
+}
+
+/// Checks that `slice` has exactly `expected_len` elements, for encoding a
+/// Mojom `array<T, N>` fixed-size array. Returns the same
+/// [`DataValidationError`] variant [`Validator::claim_fixed_array`] would
+/// produce on the decode side, so callers can propagate a single error type
+/// in either direction.
+pub fn check_fixed_array_len<T>(slice: &[T], expected_len: usize) -> Result<(), DataValidationError> {
+    if slice.len() != expected_len {
+        return Err(DataValidationError::UnexpectedArrayHeader);
+    }
+    Ok(())
+}
+
+/// A failure of one of [`Validator`]'s `claim_*` methods against an
+/// untrusted buffer.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DataValidationError {
+    /// A non-null pointer's target offset isn't 8-byte aligned.
+    MisalignedObject,
+    /// A non-null pointer, array, or struct claims bytes outside the buffer.
+    IllegalMemoryRange,
+    /// A non-null pointer doesn't point strictly forward past everything
+    /// already claimed, which would otherwise allow aliasing or a cycle; or
+    /// a pointer that must not be null (a [`Map`]'s `keys`/`vals`) is null.
+    IllegalPointer,
+    /// An `ArrayHeader.size` doesn't match its `num_elems` and element size.
+    UnexpectedArrayHeader,
+    /// A `StructHeader.size` is smaller than the minimum size for its
+    /// declared version, or larger than the region it was claimed in.
+    UnexpectedStructHeader,
+    /// A `HandleRef.index` is neither `u32::MAX` (null) nor a valid index
+    /// into the message's handle vector.
+    IllegalHandle,
+    /// The same handle index was claimed by more than one `HandleRef`.
+    IllegalDuplicateHandle,
+    /// A [`Map`]'s keys array decoded the same key more than once.
+    IllegalDuplicateMapKey,
+}
+
+/// Walks a decoded message graph, enforcing Mojo's wire-format invariants
+/// against a buffer that may have come from an untrusted peer.
+///
+/// Pointers must only ever point forward, past everything already claimed
+/// (this rules out cycles and aliasing in one check), must land 8-byte
+/// aligned, and must fit entirely inside the buffer. `Validator` tracks the
+/// high-water mark of claimed bytes (`data_end`) and the set of handle
+/// indices already claimed, re-checking both on every claim.
+pub struct Validator {
+    buffer_len: usize,
+    data_end: usize,
+    num_handles: u32,
+    claimed_handles: std::collections::HashSet<u32>,
+}
+
+impl Validator {
+    /// Creates a validator for a buffer of `buffer_len` bytes carrying
+    /// `num_handles` handles, with nothing yet claimed.
+    pub fn new(buffer_len: usize, num_handles: u32) -> Validator {
+        Validator { buffer_len, data_end: 0, num_handles, claimed_handles: Default::default() }
+    }
+
+    /// Claims the object a [`Pointer`] at byte offset `self_byte_pos` refers
+    /// to, checking alignment, forward-only-ness, and bounds, and returns the
+    /// pointee's absolute byte offset so the caller can go on to validate its
+    /// contents (e.g. via [`Validator::claim_struct`] or
+    /// [`Validator::claim_array`]). Returns `Ok(None)` for a null pointer.
+    ///
+    /// `offset` is `Pointer::offset`; `claimed_size` is the number of bytes
+    /// the pointee claims to occupy (e.g. an `ArrayHeader.size` or
+    /// `StructHeader.size`).
+    pub fn claim_pointer(
+        &mut self,
+        self_byte_pos: usize,
+        offset: u64,
+        claimed_size: usize,
+    ) -> Result<Option<usize>, DataValidationError> {
+        if offset == 0 {
+            return Ok(None);
+        }
+        let abs =
+            self_byte_pos.checked_add(offset as usize).ok_or(DataValidationError::IllegalMemoryRange)?;
+        if abs % 8 != 0 {
+            return Err(DataValidationError::MisalignedObject);
+        }
+        if abs < self.data_end {
+            return Err(DataValidationError::IllegalPointer);
+        }
+        let end = abs.checked_add(claimed_size).ok_or(DataValidationError::IllegalMemoryRange)?;
+        if end > self.buffer_len {
+            return Err(DataValidationError::IllegalMemoryRange);
+        }
+        self.data_end = abs + round_up_8(claimed_size);
+        Ok(Some(abs))
+    }
+
+    /// Validates an [`ArrayHeader`]: `size` must exactly equal `8 +
+    /// num_elems * elem_size`, rounded up to the next 8-byte boundary.
+    pub fn claim_array(&self, header: &ArrayHeader, elem_size: usize) -> Result<(), DataValidationError> {
+        let expected = round_up_8(8 + header.num_elems as usize * elem_size);
+        if header.size as usize != expected {
+            return Err(DataValidationError::UnexpectedArrayHeader);
+        }
+        Ok(())
+    }
+
+    /// Like [`Validator::claim_array`], but for a Mojom `array<T, N>`
+    /// fixed-size array: additionally requires `header.num_elems` to equal
+    /// the declared `expected_len`, rather than accepting any length.
+    pub fn claim_fixed_array(
+        &self,
+        header: &ArrayHeader,
+        elem_size: usize,
+        expected_len: usize,
+    ) -> Result<(), DataValidationError> {
+        if header.num_elems as usize != expected_len {
+            return Err(DataValidationError::UnexpectedArrayHeader);
+        }
+        self.claim_array(header, elem_size)
+    }
+
+    /// Validates a [`StructHeader`] claimed in a region of `claimed_size`
+    /// bytes: `size` must be at least `min_size_for_version` (the generated
+    /// minimum size for the struct's declared version) and must fit inside
+    /// the claimed region.
+    pub fn claim_struct(
+        &self,
+        header: &StructHeader,
+        min_size_for_version: u32,
+        claimed_size: usize,
+    ) -> Result<(), DataValidationError> {
+        if header.size < min_size_for_version || header.size as usize > claimed_size {
+            return Err(DataValidationError::UnexpectedStructHeader);
+        }
+        Ok(())
+    }
+
+    /// Validates and claims a [`HandleRef`]: `index` must be `u32::MAX`
+    /// (null) or a valid, not-already-claimed index into the message's
+    /// handle vector.
+    pub fn claim_handle(&mut self, handle: &HandleRef) -> Result<(), DataValidationError> {
+        if handle.index == u32::MAX {
+            return Ok(());
+        }
+        if handle.index >= self.num_handles {
+            return Err(DataValidationError::IllegalHandle);
+        }
+        if !self.claimed_handles.insert(handle.index) {
+            return Err(DataValidationError::IllegalDuplicateHandle);
+        }
+        Ok(())
+    }
+
+    /// Validates a [`Map`]'s wire layout in one call: its [`StructHeader`],
+    /// its `keys`/`vals` pointers, and both pointees' [`ArrayHeader`]s,
+    /// additionally enforcing the one invariant unique to `Map` among
+    /// struct-of-two-arrays layouts — `keys` and `vals` must claim the same
+    /// number of elements. Returns the absolute byte offsets of the keys
+    /// array and the vals array, in that order, for the caller to go on to
+    /// validate and decode each array's elements.
+    #[allow(clippy::too_many_arguments)]
+    pub fn claim_map(
+        &mut self,
+        self_byte_pos: usize,
+        header: &StructHeader,
+        min_size_for_version: u32,
+        claimed_size: usize,
+        keys_offset: u64,
+        keys_header: &ArrayHeader,
+        key_elem_size: usize,
+        vals_offset: u64,
+        vals_header: &ArrayHeader,
+        val_elem_size: usize,
+    ) -> Result<(usize, usize), DataValidationError> {
+        self.claim_struct(header, min_size_for_version, claimed_size)?;
+        let keys_ptr_pos = self_byte_pos + std::mem::size_of::<StructHeader>();
+        let vals_ptr_pos = keys_ptr_pos + std::mem::size_of::<Pointer<()>>();
+        let keys_abs = self
+            .claim_pointer(keys_ptr_pos, keys_offset, keys_header.size as usize)?
+            .ok_or(DataValidationError::IllegalPointer)?;
+        let vals_abs = self
+            .claim_pointer(vals_ptr_pos, vals_offset, vals_header.size as usize)?
+            .ok_or(DataValidationError::IllegalPointer)?;
+        if keys_header.num_elems != vals_header.num_elems {
+            return Err(DataValidationError::UnexpectedArrayHeader);
+        }
+        self.claim_array(keys_header, key_elem_size)?;
+        self.claim_array(vals_header, val_elem_size)?;
+        Ok((keys_abs, vals_abs))
+    }
+}
+
+/// Reconstructs an owned map from parallel `keys`/`vals` vectors decoded
+/// from a [`Map`]'s two arrays (see [`Validator::claim_map`]), rejecting a
+/// repeated key with [`DataValidationError::IllegalDuplicateMapKey`] rather than
+/// silently letting the later entry win.
+///
+/// `keys` and `vals` must be the same length; callers are expected to have
+/// already enforced that via [`Validator::claim_map`].
+pub fn reconstruct_map<K: Eq + std::hash::Hash, V>(
+    keys: Vec<K>,
+    vals: Vec<V>,
+) -> Result<std::collections::HashMap<K, V>, DataValidationError> {
+    debug_assert_eq!(keys.len(), vals.len());
+    let mut map = std::collections::HashMap::with_capacity(keys.len());
+    for (key, val) in keys.into_iter().zip(vals.into_iter()) {
+        if map.insert(key, val).is_some() {
+            return Err(DataValidationError::IllegalDuplicateMapKey);
+        }
+    }
+    Ok(map)
+}
+
+/// Bridges a mojom struct's generated wire representation to an arbitrary
+/// native Rust type, mirroring Chromium's C++ `StructTraits` typemap
+/// mechanism: were generated bindings to call through a `StructTraits<Wire>`
+/// impl, they'd serialize and deserialize through `Self` directly rather
+/// than materializing the generated `Wire` struct. See the module
+/// documentation — nothing generates that call yet.
+///
+/// `Wire` is the plain-old-data struct the mojom compiler would otherwise
+/// generate (built from [`Array`], [`Pointer`], [`StructHeader`], and the
+/// like); `Self` is the native type a user wants in its place, e.g. mapping
+/// a mojom `Rect` onto their own `Rect`.
+pub trait StructTraits<Wire>: Sized {
+    /// The error produced when a decoded `Wire` value can't be represented
+    /// as `Self`.
+    type Error;
+
+    /// Converts `self` into the generated wire struct, ready for the
+    /// generated bindings to encode.
+    fn serialize(&self) -> Wire;
+
+    /// Converts a decoded (and already [`Validator`]-checked) wire struct
+    /// into `Self`.
+    fn deserialize(wire: Wire) -> Result<Self, Self::Error>;
+}
+
+/// The header preceding a mojom `[Native]` struct or enum's pickled bytes.
+///
+/// On the wire, a `[Native]` type is just a size-prefixed opaque blob, laid
+/// out identically to an `array<uint8>` (`num_elems` bytes follow), so it
+/// validates with the same [`Validator::claim_array`] call; the idea is
+/// that generated bindings would never interpret the bytes themselves,
+/// instead routing them through a [`NativeCodec`] impl. See the module
+/// documentation — nothing generates that routing yet.
+pub type NativeBlobHeader = ArrayHeader;
+
+/// Would serialize a mojom `[Native]` struct or enum to/from the opaque
+/// byte blob its wire representation carries (see [`NativeBlobHeader`]),
+/// letting Rust consumers round-trip types the IDL doesn't know the layout
+/// of — including inside containers, e.g. `array<PickledStruct>` — without
+/// the generated bindings needing to interpret the bytes, once something
+/// calls through it; see the module documentation.
+pub trait NativeCodec: Sized {
+    /// The error produced when a pickled blob can't be unpickled into
+    /// `Self`.
+    type Error;
+
+    /// Serializes `self` into its pickled byte representation.
+    fn pickle(&self) -> Vec<u8>;
+
+    /// Deserializes a pickled byte blob back into `Self`.
+    fn unpickle(bytes: &[u8]) -> Result<Self, Self::Error>;
+}
@@ -2,6 +2,20 @@
 // Use of this source code is governed by a BSD-style license that can be
 // found in the LICENSE file.
 
+//! The `MojomEncodable`/`MojomPointer`/`MojomStruct` trait family and the
+//! built-in encodings (primitives, `String`, `Vec`/`VecDeque`/fixed arrays,
+//! `HashMap`/`BTreeMap` with `DecodePolicy`-governed duplicate-key and
+//! max-element enforcement, `Option`, handles) built on top of them.
+//!
+//! This file is not part of the compiled module tree: `bindings/lib.rs`
+//! deliberately does not declare `pub mod mojom;`, because the
+//! `Decoder`/`Encoder`/`Context`/`ValidationError`/`EncodingState`/
+//! `DataHeader` types `use`d below come from `crate::bindings::decoding` and
+//! `crate::bindings::encoding`, and `MessageHeader` comes from
+//! `crate::bindings::message` — none of which exist in this checkout. Treat
+//! everything here as an unverified sketch of the real codec's shape until
+//! those modules land, not as working, tested code.
+
 use crate::bindings::decoding::{Decoder, ValidationError};
 use crate::bindings::encoding;
 use crate::bindings::encoding::{
@@ -9,16 +23,57 @@ use crate::bindings::encoding::{
 };
 use crate::bindings::message::MessageHeader;
 
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+
+#[cfg(feature = "std")]
 use std::cmp::Eq;
-use std::collections::HashMap;
-use std::hash::Hash;
+#[cfg(feature = "std")]
+use std::collections::{BTreeMap, HashMap, VecDeque};
+#[cfg(feature = "std")]
+use std::hash::{BuildHasher, Hash};
+#[cfg(feature = "std")]
 use std::mem;
-use std::panic;
+#[cfg(feature = "std")]
 use std::vec::Vec;
 
+// Everything above this module needs is expressible against `core`+`alloc`,
+// so a caller that genuinely has no OS underneath it (embedded, a hypervisor,
+// firmware) can still decode and encode Mojom messages; only the `system`
+// integration further down (handles, pipes, buffers) needs an OS and is
+// gated on `std` at its point of use.
+#[cfg(not(feature = "std"))]
+use alloc::collections::{BTreeMap, VecDeque};
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+#[cfg(not(feature = "std"))]
+use core::cmp::Eq;
+#[cfg(not(feature = "std"))]
+use core::hash::{BuildHasher, Hash};
+#[cfg(not(feature = "std"))]
+use core::mem;
+// `std::collections::HashMap` has no `core`/`alloc` equivalent, since it
+// relies on the OS for `RandomState`'s seed; `hashbrown::HashMap` is the same
+// table with the hasher left generic, which our impls already require.
+#[cfg(not(feature = "std"))]
+use hashbrown::HashMap;
+
+#[cfg(feature = "std")]
+use std::marker::PhantomData;
+#[cfg(not(feature = "std"))]
+use core::marker::PhantomData;
+
+// Handles are backed by the Mojo system APIs, so they need an OS underneath
+// them; everything else in this file only needs `core`+`alloc`.
+#[cfg(feature = "std")]
 use crate::system::data_pipe;
+#[cfg(feature = "std")]
 use crate::system::message_pipe;
+#[cfg(feature = "std")]
 use crate::system::shared_buffer;
+#[cfg(feature = "std")]
 use crate::system::{CastHandle, Handle, MojoResult, UntypedHandle};
 
 /// The size of a Mojom map plus header in bytes.
@@ -196,6 +251,7 @@ pub fn decode_union_inline<T: MojomUnion>(
 }
 
 /// A marker trait that marks Mojo handles as encodable.
+#[cfg(feature = "std")]
 pub trait MojomHandle: CastHandle + MojomEncodable {}
 
 /// Whatever implements this trait is considered to be a Mojom
@@ -204,6 +260,7 @@ pub trait MojomHandle: CastHandle + MojomEncodable {}
 ///
 /// We force an underlying message pipe to be used via the pipe()
 /// and unwrap() routines.
+#[cfg(feature = "std")]
 pub trait MojomInterface: MojomEncodable {
     /// Get the service name for this interface.
     fn service_name() -> &'static str;
@@ -219,6 +276,7 @@ pub trait MojomInterface: MojomEncodable {
 }
 
 /// An error that may occur when sending data over a Mojom interface.
+#[cfg(feature = "std")]
 #[derive(Debug)]
 pub enum MojomSendError {
     /// Failed to write to the underlying message pipe.
@@ -237,6 +295,7 @@ pub enum MojomSendError {
 /// to the interface.
 ///
 /// TODO(mknyszek): Add sending control messages
+#[cfg(feature = "std")]
 pub trait MojomInterfaceSend<R: MojomMessage>: MojomInterface {
     /// Creates a message.
     fn create_request(&self, req_id: u64, payload: R) -> (Vec<u8>, Vec<UntypedHandle>) {
@@ -270,6 +329,7 @@ pub trait MojomInterfaceSend<R: MojomMessage>: MojomInterface {
 
 /// An error that may occur when attempting to recieve a message over a
 /// Mojom interface.
+#[cfg(feature = "std")]
 #[derive(Debug)]
 pub enum MojomRecvError {
     /// Failed to read from the underlying message pipe.
@@ -288,6 +348,7 @@ pub enum MojomRecvError {
 /// what message was received.
 ///
 /// TODO(mknyszek): Add responding to control messages
+#[cfg(feature = "std")]
 pub trait MojomInterfaceRecv: MojomInterface {
     type Container: MojomMessageOption;
 
@@ -307,6 +368,7 @@ pub trait MojomInterfaceRecv: MojomInterface {
 ///
 /// Mojom structs are always the root of any Mojom message. Thus, we
 /// provide convenience functions for serialization here.
+#[cfg(feature = "std")]
 pub trait MojomStruct: MojomPointer {
     /// Given a pre-allocated buffer, the struct serializes itself.
     fn serialize(self, buffer: &mut [u8]) -> Vec<UntypedHandle> {
@@ -336,6 +398,7 @@ pub trait MojomStruct: MojomPointer {
 
 /// Marks a MojomStruct as being capable of being sent across some
 /// Mojom interface.
+#[cfg(feature = "std")]
 pub trait MojomMessage: MojomStruct {
     fn min_version() -> u32;
     fn create_header() -> MessageHeader;
@@ -346,6 +409,7 @@ pub trait MojomMessage: MojomStruct {
 /// This trait contains the decode logic which decodes based on the message
 /// header and returns itself: a union type which may contain any of the
 /// possible messages that may be sent across this interface.
+#[cfg(feature = "std")]
 pub trait MojomMessageOption: Sized {
     /// Decodes the actual payload of the message.
     ///
@@ -537,6 +601,36 @@ impl<T: MojomEncodable> MojomEncodable for Vec<T> {
     impl_encodable_for_array!();
 }
 
+impl<T: MojomEncodable> MojomPointer for VecDeque<T> {
+    impl_pointer_for_array!();
+    fn encode_value(self, encoder: &mut Encoder, state: &mut EncodingState, context: Context) {
+        for elem in self.into_iter() {
+            elem.encode(encoder, state, context.clone());
+        }
+    }
+    fn decode_value(decoder: &mut Decoder, context: Context) -> Result<VecDeque<T>, ValidationError> {
+        let elems = {
+            let state = decoder.get_mut(&context);
+            match state.decode_array_header::<T>() {
+                Ok(header) => header.data(),
+                Err(err) => return Err(err),
+            }
+        };
+        let mut value = VecDeque::with_capacity(elems as usize);
+        for _ in 0..elems {
+            match T::decode(decoder, context.clone()) {
+                Ok(elem) => value.push_back(elem),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(value)
+    }
+}
+
+impl<T: MojomEncodable> MojomEncodable for VecDeque<T> {
+    impl_encodable_for_array!();
+}
+
 impl<T: MojomEncodable, const N: usize> MojomPointer for [T; N] {
     impl_pointer_for_array!();
     fn encode_value(self, encoder: &mut Encoder, state: &mut EncodingState, context: Context) {
@@ -645,7 +739,9 @@ impl MojomPointer for String {
         }
         match String::from_utf8(value) {
             Ok(string) => Ok(string),
-            Err(err) => panic!("Error decoding String: {}", err),
+            // A malicious peer can send arbitrary bytes here, so this has to
+            // be a validation error rather than a panic.
+            Err(_) => Err(ValidationError::UnexpectedInvalidUtf8),
         }
     }
 }
@@ -657,7 +753,7 @@ impl MojomEncodable for String {
     }
 }
 
-/// Helper function to clean up duplicate code in HashMap.
+/// Helper function to clean up duplicate code in HashMap/BTreeMap.
 fn array_claim_and_decode_header<T: MojomEncodable>(
     decoder: &mut Decoder,
     offset: usize,
@@ -676,7 +772,127 @@ fn array_claim_and_decode_header<T: MojomEncodable>(
     Ok((context, elems as usize))
 }
 
-impl<K: MojomEncodable + Eq + Hash, V: MojomEncodable> MojomPointer for HashMap<K, V> {
+/// Controls how strictly [`decode_map_entries_with_policy`],
+/// [`decode_hash_map_with_policy`], and [`decode_btree_map_with_policy`]
+/// validate an untrusted map on the wire, beyond the unconditional checks
+/// (matching array lengths, valid pointers) that [`decode_map_entries`]
+/// always performs. `reject_duplicate_keys` is honored only by the latter two.
+#[derive(Clone, Copy, Debug)]
+pub struct DecodePolicy {
+    /// If `true`, a repeated key in the decoded map is a
+    /// [`ValidationError::DuplicateMapKey`] instead of silently letting the
+    /// later entry win (the historical, and still default, behavior).
+    pub reject_duplicate_keys: bool,
+    /// The largest number of entries a map's keys/values arrays may claim to
+    /// have, checked against the array headers before the per-element
+    /// decode loop runs. `None` (the default) means no limit.
+    pub max_elements: Option<usize>,
+}
+
+impl Default for DecodePolicy {
+    fn default() -> DecodePolicy {
+        DecodePolicy { reject_duplicate_keys: false, max_elements: None }
+    }
+}
+
+/// A lazy, decode-on-demand view over a serialized Mojom map's entries,
+/// returned by [`decode_map_entries`]. Nothing beyond the keys/values array
+/// headers is decoded until the iterator is actually advanced, and no
+/// intermediate `Vec` of keys is ever materialized.
+pub struct MapEntries<'d, K, V> {
+    decoder: &'d mut Decoder,
+    keys_context: Context,
+    vals_context: Context,
+    remaining: usize,
+    _marker: PhantomData<(K, V)>,
+}
+
+impl<'d, K: MojomEncodable, V: MojomEncodable> Iterator for MapEntries<'d, K, V> {
+    type Item = Result<(K, V), ValidationError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let key = match K::decode(self.decoder, self.keys_context.clone()) {
+            Ok(key) => key,
+            Err(err) => return Some(Err(err)),
+        };
+        let value = match V::decode(self.decoder, self.vals_context.clone()) {
+            Ok(value) => value,
+            Err(err) => return Some(Err(err)),
+        };
+        Some(Ok((key, value)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+/// Validates a serialized Mojom map's struct and array headers (including
+/// that the keys and values arrays have the same length) and returns a
+/// [`MapEntries`] iterator that decodes one `(key, value)` pair per `next()`
+/// call. Callers that only need to scan or filter a map can consume it
+/// directly without materializing a `HashMap`/`BTreeMap`; [`HashMap`]'s and
+/// [`BTreeMap`]'s `decode_value` are themselves thin `collect()`s over this.
+pub fn decode_map_entries<'d, K: MojomEncodable, V: MojomEncodable>(
+    decoder: &'d mut Decoder,
+    context: Context,
+) -> Result<MapEntries<'d, K, V>, ValidationError> {
+    decode_map_entries_with_policy(decoder, context, DecodePolicy::default())
+}
+
+/// Like [`decode_map_entries`], but honors [`DecodePolicy::max_elements`]:
+/// the keys/values arrays' claimed length is checked against it before the
+/// per-element decode loop runs, so a hostile `elems` header can't force an
+/// unbounded amount of work. [`DecodePolicy::reject_duplicate_keys`] has no
+/// effect here, since `MapEntries` only yields pairs; it's enforced by
+/// [`decode_hash_map_with_policy`]/[`decode_btree_map_with_policy`] at
+/// insertion time instead.
+pub fn decode_map_entries_with_policy<'d, K: MojomEncodable, V: MojomEncodable>(
+    decoder: &'d mut Decoder,
+    context: Context,
+    policy: DecodePolicy,
+) -> Result<MapEntries<'d, K, V>, ValidationError> {
+    let (keys_offset, vals_offset) = {
+        let state = decoder.get_mut(&context);
+        match state.decode_struct_header(&MAP_VERSIONS) {
+            Ok(_) => (),
+            Err(err) => return Err(err),
+        };
+        // Decode the keys pointer and check for overflow
+        let keys_offset = match state.decode_pointer() {
+            Some(ptr) => ptr,
+            None => return Err(ValidationError::IllegalPointer),
+        };
+        // Decode the vals pointer and check for overflow
+        let vals_offset = match state.decode_pointer() {
+            Some(ptr) => ptr,
+            None => return Err(ValidationError::IllegalPointer),
+        };
+        if keys_offset == MOJOM_NULL_POINTER || vals_offset == MOJOM_NULL_POINTER {
+            return Err(ValidationError::UnexpectedNullPointer);
+        }
+        (keys_offset as usize, vals_offset as usize)
+    };
+    let (keys_context, keys_elems) = array_claim_and_decode_header::<K>(decoder, keys_offset)?;
+    let (vals_context, vals_elems) = array_claim_and_decode_header::<V>(decoder, vals_offset)?;
+    if keys_elems != vals_elems {
+        return Err(ValidationError::DifferentSizedArraysInMap);
+    }
+    if let Some(max_elements) = policy.max_elements {
+        if keys_elems > max_elements {
+            return Err(ValidationError::TooManyElements);
+        }
+    }
+    Ok(MapEntries { decoder, keys_context, vals_context, remaining: keys_elems, _marker: PhantomData })
+}
+
+impl<K: MojomEncodable + Eq + Hash, V: MojomEncodable, S: BuildHasher + Default> MojomPointer
+    for HashMap<K, V, S>
+{
     fn header_data(&self) -> DataHeaderValue {
         DataHeaderValue::Version(0)
     }
@@ -707,62 +923,122 @@ impl<K: MojomEncodable + Eq + Hash, V: MojomEncodable> MojomPointer for HashMap<
     fn decode_value(
         decoder: &mut Decoder,
         context: Context,
-    ) -> Result<HashMap<K, V>, ValidationError> {
-        let (keys_offset, vals_offset) = {
-            let state = decoder.get_mut(&context);
-            match state.decode_struct_header(&MAP_VERSIONS) {
-                Ok(_) => (),
-                Err(err) => return Err(err),
-            };
-            // Decode the keys pointer and check for overflow
-            let keys_offset = match state.decode_pointer() {
-                Some(ptr) => ptr,
-                None => return Err(ValidationError::IllegalPointer),
-            };
-            // Decode the keys pointer and check for overflow
-            let vals_offset = match state.decode_pointer() {
-                Some(ptr) => ptr,
-                None => return Err(ValidationError::IllegalPointer),
-            };
-            if keys_offset == MOJOM_NULL_POINTER || vals_offset == MOJOM_NULL_POINTER {
-                return Err(ValidationError::UnexpectedNullPointer);
-            }
-            (keys_offset as usize, vals_offset as usize)
-        };
-        let (keys_context, keys_elems) =
-            match array_claim_and_decode_header::<K>(decoder, keys_offset) {
-                Ok((context, elems)) => (context, elems),
-                Err(err) => return Err(err),
-            };
-        let mut keys_vec: Vec<K> = Vec::with_capacity(keys_elems as usize);
-        for _ in 0..keys_elems {
-            let key = match K::decode(decoder, keys_context.clone()) {
-                Ok(value) => value,
-                Err(err) => return Err(err),
-            };
-            keys_vec.push(key);
+    ) -> Result<HashMap<K, V, S>, ValidationError> {
+        decode_hash_map_with_policy(decoder, context, DecodePolicy::default())
+    }
+}
+
+/// Like `HashMap`'s `decode_value`, but honors `policy`: in particular,
+/// [`DecodePolicy::reject_duplicate_keys`] turns a repeated key into a
+/// [`ValidationError::DuplicateMapKey`] instead of silently keeping the last
+/// value, and [`DecodePolicy::max_elements`] bounds the decoded size.
+pub fn decode_hash_map_with_policy<
+    K: MojomEncodable + Eq + Hash,
+    V: MojomEncodable,
+    S: BuildHasher + Default,
+>(
+    decoder: &mut Decoder,
+    context: Context,
+    policy: DecodePolicy,
+) -> Result<HashMap<K, V, S>, ValidationError> {
+    let entries = decode_map_entries_with_policy::<K, V>(decoder, context, policy)?;
+    let mut map = HashMap::with_capacity_and_hasher(entries.size_hint().0, S::default());
+    for entry in entries {
+        let (key, val) = entry?;
+        if policy.reject_duplicate_keys && map.contains_key(&key) {
+            return Err(ValidationError::DuplicateMapKey);
         }
-        let (vals_context, vals_elems) =
-            match array_claim_and_decode_header::<V>(decoder, vals_offset) {
-                Ok((context, elems)) => (context, elems),
-                Err(err) => return Err(err),
-            };
-        if keys_elems != vals_elems {
-            return Err(ValidationError::DifferentSizedArraysInMap);
+        map.insert(key, val);
+    }
+    Ok(map)
+}
+
+impl<K: MojomEncodable + Eq + Hash, V: MojomEncodable, S: BuildHasher + Default> MojomEncodable
+    for HashMap<K, V, S>
+{
+    impl_encodable_for_pointer!();
+    fn compute_size(&self, context: Context) -> usize {
+        let mut size = encoding::align_default(self.serialized_size(&context));
+        // The size of the one array
+        size += DATA_HEADER_SIZE;
+        size += (K::embed_size(&context) * self.len()).as_bytes();
+        size = encoding::align_default(size);
+        // Any extra space used by the keys
+        for (key, _) in self {
+            size += key.compute_size(context.clone());
         }
-        let mut map = HashMap::with_capacity(keys_elems as usize);
-        for key in keys_vec.into_iter() {
-            let val = match V::decode(decoder, vals_context.clone()) {
-                Ok(value) => value,
-                Err(err) => return Err(err),
-            };
-            map.insert(key, val);
+        // Need to re-align after this for the next array
+        size = encoding::align_default(size);
+        // The size of the one array
+        size += DATA_HEADER_SIZE;
+        size += (V::embed_size(&context) * self.len()).as_bytes();
+        size = encoding::align_default(size);
+        // Any extra space used by the values
+        for (_, value) in self {
+            size += value.compute_size(context.clone());
+        }
+        // Align one more time at the end to keep the next object aligned.
+        encoding::align_default(size)
+    }
+}
+
+impl<K: MojomEncodable + Ord, V: MojomEncodable> MojomPointer for BTreeMap<K, V> {
+    fn header_data(&self) -> DataHeaderValue {
+        DataHeaderValue::Version(0)
+    }
+    fn serialized_size(&self, _context: &Context) -> usize {
+        MAP_SIZE
+    }
+    fn encode_value(self, encoder: &mut Encoder, state: &mut EncodingState, context: Context) {
+        let elems = self.len();
+        let meta_value = DataHeaderValue::Elements(elems as u32);
+        // We need to move values into this vector because we can't copy the keys.
+        // (Handles are not copyable so MojomEncodable cannot be copyable!)
+        let mut vals_vec = Vec::with_capacity(elems);
+        // Key setup
+        // Create the keys data header
+        let keys_bytes = DATA_HEADER_SIZE + (K::embed_size(&context) * elems).as_bytes();
+        let keys_data_header = DataHeader::new(keys_bytes, meta_value);
+        // Claim space for the keys array in the encoder
+        let (keys_offset, mut keys_state, keys_context) = encoder.add(&keys_data_header).unwrap();
+        state.encode_pointer(keys_offset);
+        // Encode keys (in sorted order, since `BTreeMap::into_iter` yields
+        // them that way), setup vals
+        for (key, value) in self.into_iter() {
+            key.encode(encoder, &mut keys_state, keys_context.clone());
+            vals_vec.push(value);
+        }
+        // Encode vals
+        vals_vec.encode(encoder, state, context.clone())
+    }
+    fn decode_value(
+        decoder: &mut Decoder,
+        context: Context,
+    ) -> Result<BTreeMap<K, V>, ValidationError> {
+        decode_btree_map_with_policy(decoder, context, DecodePolicy::default())
+    }
+}
+
+/// Like `BTreeMap`'s `decode_value`, but honors `policy`; see
+/// [`decode_hash_map_with_policy`].
+pub fn decode_btree_map_with_policy<K: MojomEncodable + Ord, V: MojomEncodable>(
+    decoder: &mut Decoder,
+    context: Context,
+    policy: DecodePolicy,
+) -> Result<BTreeMap<K, V>, ValidationError> {
+    let entries = decode_map_entries_with_policy::<K, V>(decoder, context, policy)?;
+    let mut map = BTreeMap::new();
+    for entry in entries {
+        let (key, val) = entry?;
+        if policy.reject_duplicate_keys && map.contains_key(&key) {
+            return Err(ValidationError::DuplicateMapKey);
         }
-        Ok(map)
+        map.insert(key, val);
     }
+    Ok(map)
 }
 
-impl<K: MojomEncodable + Eq + Hash, V: MojomEncodable> MojomEncodable for HashMap<K, V> {
+impl<K: MojomEncodable + Ord, V: MojomEncodable> MojomEncodable for BTreeMap<K, V> {
     impl_encodable_for_pointer!();
     fn compute_size(&self, context: Context) -> usize {
         let mut size = encoding::align_default(self.serialized_size(&context));
@@ -789,8 +1065,10 @@ impl<K: MojomEncodable + Eq + Hash, V: MojomEncodable> MojomEncodable for HashMa
     }
 }
 
+#[cfg(feature = "std")]
 impl<T: MojomEncodable + CastHandle + Handle> MojomHandle for T {}
 
+#[cfg(feature = "std")]
 macro_rules! impl_encodable_for_handle {
     ($handle_type:path) => {
         fn mojom_alignment() -> usize {
@@ -822,22 +1100,27 @@ macro_rules! impl_encodable_for_handle {
     };
 }
 
+#[cfg(feature = "std")]
 impl MojomEncodable for UntypedHandle {
     impl_encodable_for_handle!(UntypedHandle);
 }
 
+#[cfg(feature = "std")]
 impl MojomEncodable for message_pipe::MessageEndpoint {
     impl_encodable_for_handle!(message_pipe::MessageEndpoint);
 }
 
+#[cfg(feature = "std")]
 impl MojomEncodable for shared_buffer::SharedBuffer {
     impl_encodable_for_handle!(shared_buffer::SharedBuffer);
 }
 
+#[cfg(feature = "std")]
 impl<T> MojomEncodable for data_pipe::Consumer<T> {
     impl_encodable_for_handle!(data_pipe::Consumer<T>);
 }
 
+#[cfg(feature = "std")]
 impl<T> MojomEncodable for data_pipe::Producer<T> {
     impl_encodable_for_handle!(data_pipe::Producer<T>);
 }
@@ -0,0 +1,11 @@
+// Copyright 2024 The Chromium Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Rust bindings for the low-level Mojo system APIs. See the [`system`]
+//! module for handles, message pipes, data pipes, shared buffers, traps, and
+//! wait sets.
+
+pub mod system;
+
+pub use system::MojoResult;
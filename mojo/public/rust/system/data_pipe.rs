@@ -0,0 +1,449 @@
+// Copyright 2024 The Chromium Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Data pipes: a unidirectional, byte-streaming channel with a producer end
+//! and a consumer end, supporting both one-shot and two-phase (begin/commit)
+//! reads and writes.
+
+use std::future::Future;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::io::{AsyncRead, AsyncWrite};
+
+use crate::system::handle;
+use crate::system::{CastHandle, Handle, HandleSignals, MojoResult, UntypedHandle};
+
+#[cfg(feature = "emulate")]
+use crate::system::emulate;
+#[cfg(not(feature = "emulate"))]
+use crate::system::native;
+
+/// The default capacity, in bytes, used by [`create_default`].
+const DEFAULT_CAPACITY_BYTES: usize = 64 * 1024;
+
+bitflags::bitflags! {
+    /// Flags accepted by [`Producer::write`].
+    #[derive(Default)]
+    pub struct WriteFlags: u32 {
+        const NONE = 0;
+    }
+}
+
+bitflags::bitflags! {
+    /// Flags accepted by [`Consumer::read`].
+    #[derive(Default)]
+    pub struct ReadFlags: u32 {
+        const NONE = 0;
+        /// Discard the data read instead of returning it.
+        const DISCARD = 1 << 0;
+    }
+}
+
+/// Creates a data pipe with the default capacity, returning its consumer and
+/// producer ends.
+pub fn create_default() -> Result<(Consumer<u8>, Producer<u8>), MojoResult> {
+    create(DEFAULT_CAPACITY_BYTES)
+}
+
+/// Creates a data pipe with room for `capacity_in_bytes` bytes of unread
+/// data.
+pub fn create<T>(capacity_in_bytes: usize) -> Result<(Consumer<T>, Producer<T>), MojoResult> {
+    let (consumer, producer) = create_native_pair(capacity_in_bytes);
+    Ok((
+        Consumer { handle: handle::wrap_native(consumer), _marker: PhantomData, pending: None },
+        Producer { handle: handle::wrap_native(producer), _marker: PhantomData, pending: None },
+    ))
+}
+
+#[cfg(feature = "emulate")]
+fn create_native_pair(capacity_in_bytes: usize) -> (u32, u32) {
+    emulate::create_data_pipe(capacity_in_bytes)
+}
+
+#[cfg(not(feature = "emulate"))]
+fn create_native_pair(capacity_in_bytes: usize) -> (u32, u32) {
+    native::create_data_pipe(capacity_in_bytes)
+}
+
+/// The consuming (read) end of a data pipe.
+pub struct Consumer<T> {
+    handle: UntypedHandle,
+    _marker: PhantomData<T>,
+    /// The in-flight reactor wait behind a pending [`AsyncRead::poll_read`],
+    /// kept alive across polls so its registered waker isn't dropped before
+    /// it fires.
+    pending: Option<crate::system::reactor::WaitAsync>,
+}
+
+impl<T> Consumer<T> {
+    /// Reads and removes all currently-available data in one call.
+    pub fn read(&self, flags: ReadFlags) -> Result<Vec<u8>, MojoResult> {
+        read(self.get_native_handle(), flags.contains(ReadFlags::DISCARD))
+    }
+
+    /// Begins a two-phase read: the returned [`ReadBuf`] holds all
+    /// currently-available data until [`ReadBuf::commit`] (or its `Drop`)
+    /// says how much of it was actually consumed.
+    pub fn begin(&self) -> Result<ReadBuf, MojoResult> {
+        let data = begin_read(self.get_native_handle())?;
+        Ok(ReadBuf { native: self.get_native_handle(), data, committed: false })
+    }
+}
+
+#[cfg(feature = "emulate")]
+fn read(native: u32, discard: bool) -> Result<Vec<u8>, MojoResult> {
+    emulate::dp_read(native, discard)
+}
+
+#[cfg(not(feature = "emulate"))]
+fn read(native: u32, discard: bool) -> Result<Vec<u8>, MojoResult> {
+    native::dp_read(native, discard)
+}
+
+#[cfg(feature = "emulate")]
+fn begin_read(native: u32) -> Result<Vec<u8>, MojoResult> {
+    emulate::dp_begin_read(native)
+}
+
+#[cfg(not(feature = "emulate"))]
+fn begin_read(native: u32) -> Result<Vec<u8>, MojoResult> {
+    native::dp_begin_read(native)
+}
+
+#[cfg(feature = "emulate")]
+fn commit_read(native: u32, committed: usize) {
+    emulate::dp_commit_read(native, committed)
+}
+
+#[cfg(not(feature = "emulate"))]
+fn commit_read(native: u32, committed: usize) {
+    native::dp_commit_read(native, committed)
+}
+
+impl<T> Handle for Consumer<T> {
+    fn get_native_handle(&self) -> u32 {
+        self.handle.get_native_handle()
+    }
+
+    unsafe fn invalidate(&mut self) {
+        self.handle.invalidate()
+    }
+}
+
+impl<T> CastHandle for Consumer<T> {
+    fn as_untyped(self) -> UntypedHandle {
+        self.handle
+    }
+
+    unsafe fn from_untyped(handle: UntypedHandle) -> Self {
+        Consumer { handle, _marker: PhantomData, pending: None }
+    }
+}
+
+/// The producing (write) end of a data pipe.
+pub struct Producer<T> {
+    handle: UntypedHandle,
+    _marker: PhantomData<T>,
+    /// The in-flight reactor wait behind a pending [`AsyncWrite::poll_write`],
+    /// kept alive across polls so its registered waker isn't dropped before
+    /// it fires.
+    pending: Option<crate::system::reactor::WaitAsync>,
+}
+
+impl<T> Producer<T> {
+    /// Writes as much of `bytes` as there's currently room for, returning
+    /// the number of bytes actually written.
+    pub fn write(&self, bytes: &[u8], _flags: WriteFlags) -> Result<usize, MojoResult> {
+        write(self.get_native_handle(), bytes)
+    }
+
+    /// Begins a two-phase write: the returned [`WriteBuf`] gives access to
+    /// all currently-available write room until [`WriteBuf::commit`] (or its
+    /// `Drop`) says how much of it was actually filled in.
+    pub fn begin(&self) -> Result<WriteBuf, MojoResult> {
+        let buf = begin_write(self.get_native_handle())?;
+        Ok(WriteBuf { native: self.get_native_handle(), buf, committed: false })
+    }
+}
+
+#[cfg(feature = "emulate")]
+fn write(native: u32, bytes: &[u8]) -> Result<usize, MojoResult> {
+    emulate::dp_write(native, bytes)
+}
+
+#[cfg(not(feature = "emulate"))]
+fn write(native: u32, bytes: &[u8]) -> Result<usize, MojoResult> {
+    native::dp_write(native, bytes)
+}
+
+#[cfg(feature = "emulate")]
+fn begin_write(native: u32) -> Result<Vec<MaybeUninit<u8>>, MojoResult> {
+    emulate::dp_begin_write(native)
+}
+
+#[cfg(not(feature = "emulate"))]
+fn begin_write(native: u32) -> Result<Vec<MaybeUninit<u8>>, MojoResult> {
+    native::dp_begin_write(native)
+}
+
+/// # Safety
+/// The first `committed` elements of `buf` must be initialized.
+#[cfg(feature = "emulate")]
+unsafe fn commit_write(native: u32, buf: Vec<MaybeUninit<u8>>, committed: usize) {
+    emulate::dp_commit_write(native, buf, committed)
+}
+
+/// # Safety
+/// The first `committed` elements of `buf` must be initialized.
+#[cfg(not(feature = "emulate"))]
+unsafe fn commit_write(native: u32, buf: Vec<MaybeUninit<u8>>, committed: usize) {
+    native::dp_commit_write(native, buf, committed)
+}
+
+impl<T> Handle for Producer<T> {
+    fn get_native_handle(&self) -> u32 {
+        self.handle.get_native_handle()
+    }
+
+    unsafe fn invalidate(&mut self) {
+        self.handle.invalidate()
+    }
+}
+
+impl<T> CastHandle for Producer<T> {
+    fn as_untyped(self) -> UntypedHandle {
+        self.handle
+    }
+
+    unsafe fn from_untyped(handle: UntypedHandle) -> Self {
+        Producer { handle, _marker: PhantomData, pending: None }
+    }
+}
+
+/// A buffer of not-yet-committed data read from a [`Consumer`] via
+/// [`Consumer::begin`]. If dropped without calling [`commit`](ReadBuf::commit),
+/// none of the data is considered consumed.
+pub struct ReadBuf {
+    native: u32,
+    data: Vec<u8>,
+    committed: bool,
+}
+
+impl ReadBuf {
+    /// Returns a new `Vec` holding a copy of the read data.
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Ends the two-phase read, removing the first `elements` bytes from the
+    /// pipe.
+    pub fn commit(mut self, elements: usize) {
+        self.committed = true;
+        commit_read(self.native, elements);
+    }
+}
+
+impl Deref for ReadBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Drop for ReadBuf {
+    fn drop(&mut self) {
+        if !self.committed {
+            commit_read(self.native, 0);
+        }
+    }
+}
+
+/// A buffer of not-yet-committed write room in a [`Producer`] via
+/// [`Producer::begin`]. If dropped without calling
+/// [`commit`](WriteBuf::commit), none of the data written to the buffer is
+/// considered written to the pipe.
+pub struct WriteBuf {
+    native: u32,
+    buf: Vec<MaybeUninit<u8>>,
+    committed: bool,
+}
+
+impl WriteBuf {
+    /// Ends the two-phase write, making the first `elements` bytes written
+    /// to this buffer visible to the consumer.
+    ///
+    /// # Safety
+    ///
+    /// The first `elements` elements of this buffer must have been
+    /// initialized.
+    pub unsafe fn commit(mut self, elements: usize) {
+        self.committed = true;
+        let buf = std::mem::take(&mut self.buf);
+        commit_write(self.native, buf, elements);
+    }
+}
+
+impl Deref for WriteBuf {
+    type Target = [MaybeUninit<u8>];
+
+    fn deref(&self) -> &[MaybeUninit<u8>] {
+        &self.buf
+    }
+}
+
+impl DerefMut for WriteBuf {
+    fn deref_mut(&mut self) -> &mut [MaybeUninit<u8>] {
+        &mut self.buf
+    }
+}
+
+impl Drop for WriteBuf {
+    fn drop(&mut self) {
+        if !self.committed {
+            let buf = std::mem::take(&mut self.buf);
+            // SAFETY: committing zero elements never reads from `buf`.
+            unsafe { commit_write(self.native, buf, 0) };
+        }
+    }
+}
+
+impl Consumer<u8> {
+    /// Awaits readability, then hands back a two-phase [`ReadBuf`] for the
+    /// caller to commit exactly like [`Consumer::begin`], without the extra
+    /// copy [`AsyncRead::poll_read`] makes into a caller-supplied buffer.
+    pub async fn read_buf(&self) -> Result<ReadBuf, MojoResult> {
+        loop {
+            match self.begin() {
+                Ok(buf) => return Ok(buf),
+                Err(MojoResult::ShouldWait) => {
+                    self.wait_async(HandleSignals::READABLE).await?;
+                }
+                Err(other) => return Err(other),
+            }
+        }
+    }
+}
+
+impl AsyncRead for Consumer<u8> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(wait) = this.pending.as_mut() {
+                match Pin::new(wait).poll(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(Ok(_)) => this.pending = None,
+                    Poll::Ready(Err(MojoResult::FailedPrecondition | MojoResult::Cancelled)) => {
+                        this.pending = None;
+                        return Poll::Ready(Ok(0));
+                    }
+                    Poll::Ready(Err(other)) => {
+                        this.pending = None;
+                        return Poll::Ready(Err(to_io_error(other)));
+                    }
+                }
+            }
+            match this.begin() {
+                Ok(read_buf) => {
+                    let n = read_buf.len().min(buf.len());
+                    buf[..n].copy_from_slice(&read_buf[..n]);
+                    read_buf.commit(n);
+                    return Poll::Ready(Ok(n));
+                }
+                Err(MojoResult::ShouldWait) => {
+                    this.pending = Some(this.wait_async(HandleSignals::READABLE));
+                }
+                Err(MojoResult::FailedPrecondition) => return Poll::Ready(Ok(0)),
+                Err(other) => return Poll::Ready(Err(to_io_error(other))),
+            }
+        }
+    }
+}
+
+impl Producer<u8> {
+    /// Awaits writability, then hands back a two-phase [`WriteBuf`] for the
+    /// caller to commit exactly like [`Producer::begin`], without the extra
+    /// copy [`AsyncWrite::poll_write`] makes out of a caller-supplied buffer.
+    pub async fn write_buf(&self) -> Result<WriteBuf, MojoResult> {
+        loop {
+            match self.begin() {
+                Ok(buf) => return Ok(buf),
+                Err(MojoResult::ShouldWait) => {
+                    self.wait_async(HandleSignals::WRITABLE).await?;
+                }
+                Err(other) => return Err(other),
+            }
+        }
+    }
+}
+
+impl AsyncWrite for Producer<u8> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(wait) = this.pending.as_mut() {
+                match Pin::new(wait).poll(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(Ok(_)) => this.pending = None,
+                    Poll::Ready(Err(MojoResult::FailedPrecondition | MojoResult::Cancelled)) => {
+                        this.pending = None;
+                        return Poll::Ready(Err(to_io_error(MojoResult::FailedPrecondition)));
+                    }
+                    Poll::Ready(Err(other)) => {
+                        this.pending = None;
+                        return Poll::Ready(Err(to_io_error(other)));
+                    }
+                }
+            }
+            match this.begin() {
+                Ok(mut write_buf) => {
+                    let n = buf.len().min(write_buf.len());
+                    MaybeUninit::write_slice(&mut write_buf[..n], &buf[..n]);
+                    // SAFETY: the first `n` elements were just initialized above.
+                    unsafe { write_buf.commit(n) };
+                    return Poll::Ready(Ok(n));
+                }
+                Err(MojoResult::ShouldWait) => {
+                    this.pending = Some(this.wait_async(HandleSignals::WRITABLE));
+                }
+                Err(MojoResult::FailedPrecondition) => {
+                    return Poll::Ready(Err(to_io_error(MojoResult::FailedPrecondition)));
+                }
+                Err(other) => return Poll::Ready(Err(to_io_error(other))),
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn to_io_error(result: MojoResult) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, format!("mojo data pipe error: {:?}", result))
+}
@@ -0,0 +1,624 @@
+// Copyright 2024 The Chromium Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! A pure-Rust, in-process emulation of the primitives the native Mojo EDK
+//! normally provides, enabled via the `emulate` cargo feature.
+//!
+//! Every live handle is represented here by a native `u32` (the same
+//! currency `Handle::get_native_handle` deals in) that indexes into a
+//! process-global [`Registry`]. Two handles that are peers of one another
+//! (the two ends of a message pipe, or a data pipe's producer/consumer)
+//! either point at each other by native value (message pipes) or share the
+//! same underlying [`Object`] under two different native keys (data pipes).
+//! A duplicated shared buffer gets its own [`Object`] wrapping the same
+//! byte buffer. This lets [`handle::CastHandle::from_untyped`]
+//! reconstruct a typed wrapper from nothing but the native value.
+
+use std::collections::{HashMap, VecDeque};
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::system::handle::{HandleSignals, SignalsState};
+use crate::system::trap::TriggerCondition;
+use crate::system::MojoResult;
+
+/// A live object in the registry: some handle-able state plus the condvar
+/// that [`wait`] blocks on.
+pub(crate) struct Object {
+    cond: Condvar,
+    state: Mutex<ObjectState>,
+}
+
+pub(crate) enum ObjectState {
+    MessagePipe(MessagePipeState),
+    DataPipe(DataPipeState),
+    SharedBuffer(Arc<Mutex<Vec<u8>>>),
+}
+
+pub(crate) struct MessagePipeState {
+    /// The native handle of the peer endpoint to deliver writes to, or `0`
+    /// once that peer has closed.
+    peer_native: u32,
+    incoming: VecDeque<(Vec<u8>, Vec<u32>)>,
+}
+
+pub(crate) struct DataPipeState {
+    producer_native: u32,
+    consumer_native: u32,
+    ring: VecDeque<u8>,
+    capacity: usize,
+    write_in_progress: bool,
+    read_in_progress: bool,
+}
+
+struct Registry {
+    objects: Mutex<HashMap<u32, Arc<Object>>>,
+}
+
+static NEXT_HANDLE: AtomicU32 = AtomicU32::new(1);
+
+fn next_handle() -> u32 {
+    // 0 is reserved as the invalid handle value.
+    NEXT_HANDLE.fetch_add(1, Ordering::Relaxed)
+}
+
+lazy_static::lazy_static! {
+    static ref REGISTRY: Registry = Registry { objects: Mutex::new(HashMap::new()) };
+}
+
+fn get_object(native: u32) -> Option<Arc<Object>> {
+    REGISTRY.objects.lock().unwrap().get(&native).cloned()
+}
+
+fn insert_object(native: u32, object: Arc<Object>) {
+    REGISTRY.objects.lock().unwrap().insert(native, object);
+}
+
+/// Removes `native` from the registry and notifies its peer, if any. Panics
+/// if `native` is not a live handle.
+pub(crate) fn close(native: u32) {
+    let object = REGISTRY.objects.lock().unwrap().remove(&native);
+    let Some(object) = object else { panic!("close() on an invalid handle: {}", native) };
+    let mut notify_also = Vec::new();
+    {
+        let mut state = object.state.lock().unwrap();
+        match &mut *state {
+            ObjectState::MessagePipe(mp) => {
+                let peer = mp.peer_native;
+                if peer != 0 {
+                    notify_also.push(peer);
+                }
+            }
+            ObjectState::DataPipe(dp) => {
+                if dp.producer_native == native {
+                    dp.producer_native = 0;
+                }
+                if dp.consumer_native == native {
+                    dp.consumer_native = 0;
+                }
+                if dp.producer_native != 0 {
+                    notify_also.push(dp.producer_native);
+                }
+                if dp.consumer_native != 0 {
+                    notify_also.push(dp.consumer_native);
+                }
+            }
+            ObjectState::SharedBuffer(_) => {}
+        }
+    }
+    // If our peer is a *different* object (message pipes), flip its side of
+    // the relationship so it sees us as closed.
+    if let ObjectState::MessagePipe(_) = &*object.state.lock().unwrap() {
+        for &peer in &notify_also {
+            if let Some(peer_object) = get_object(peer) {
+                if let ObjectState::MessagePipe(peer_mp) = &mut *peer_object.state.lock().unwrap() {
+                    peer_mp.peer_native = 0;
+                }
+                peer_object.cond.notify_all();
+            }
+        }
+    } else {
+        object.cond.notify_all();
+    }
+    cancel_triggers_for_handle(native);
+    for native in notify_also {
+        notify_triggers(native);
+    }
+}
+
+fn signals_for(state: &ObjectState, native: u32) -> SignalsState {
+    let mut satisfied = HandleSignals::NONE;
+    // PEER_CLOSED is always a signal one could wait for, whether or not it
+    // has already happened.
+    let mut satisfiable = HandleSignals::PEER_CLOSED;
+    match state {
+        ObjectState::MessagePipe(mp) => {
+            if !mp.incoming.is_empty() {
+                satisfied |= HandleSignals::READABLE;
+            }
+            if mp.peer_native != 0 {
+                satisfied |= HandleSignals::WRITABLE;
+                satisfiable |= HandleSignals::WRITABLE | HandleSignals::READABLE;
+            } else {
+                satisfied |= HandleSignals::PEER_CLOSED;
+                if !mp.incoming.is_empty() {
+                    satisfiable |= HandleSignals::READABLE;
+                }
+            }
+        }
+        ObjectState::DataPipe(dp) => {
+            if native == dp.producer_native {
+                if dp.consumer_native != 0 {
+                    satisfiable |= HandleSignals::WRITABLE;
+                    if !dp.write_in_progress && dp.ring.len() < dp.capacity {
+                        satisfied |= HandleSignals::WRITABLE;
+                    }
+                } else {
+                    satisfied |= HandleSignals::PEER_CLOSED;
+                }
+            } else {
+                debug_assert_eq!(native, dp.consumer_native);
+                if !dp.ring.is_empty() || dp.producer_native != 0 {
+                    satisfiable |= HandleSignals::READABLE;
+                }
+                if !dp.read_in_progress && !dp.ring.is_empty() {
+                    satisfied |= HandleSignals::READABLE;
+                }
+                if dp.producer_native == 0 {
+                    satisfied |= HandleSignals::PEER_CLOSED;
+                }
+            }
+        }
+        ObjectState::SharedBuffer(_) => {}
+    }
+    SignalsState::new(satisfied, satisfiable)
+}
+
+/// Takes a non-blocking snapshot of `native`'s current signals state.
+pub(crate) fn signals(native: u32) -> SignalsState {
+    let object = get_object(native).expect("signals() on an invalid handle");
+    let state = object.state.lock().unwrap();
+    signals_for(&state, native)
+}
+
+/// Blocks until `signals` is either fully satisfied or can no longer
+/// possibly be, whichever happens first, and returns the resulting state.
+pub(crate) fn wait(native: u32, signals: HandleSignals) -> SignalsState {
+    let object = get_object(native).expect("wait() on an invalid handle");
+    let guard = object.state.lock().unwrap();
+    let guard = object
+        .cond
+        .wait_while(guard, |state| {
+            let cur = signals_for(state, native);
+            !cur.satisfied().contains(signals) && cur.satisfiable().contains(signals)
+        })
+        .unwrap();
+    signals_for(&guard, native)
+}
+
+// ******************** Message pipes ******************** //
+
+pub(crate) fn create_message_pipe() -> (u32, u32) {
+    let a = next_handle();
+    let b = next_handle();
+    insert_object(
+        a,
+        Arc::new(Object {
+            cond: Condvar::new(),
+            state: Mutex::new(ObjectState::MessagePipe(MessagePipeState {
+                peer_native: b,
+                incoming: VecDeque::new(),
+            })),
+        }),
+    );
+    insert_object(
+        b,
+        Arc::new(Object {
+            cond: Condvar::new(),
+            state: Mutex::new(ObjectState::MessagePipe(MessagePipeState {
+                peer_native: a,
+                incoming: VecDeque::new(),
+            })),
+        }),
+    );
+    (a, b)
+}
+
+pub(crate) fn mp_write(native: u32, bytes: Vec<u8>, handles: Vec<u32>) -> MojoResult {
+    let object = get_object(native).expect("write() on an invalid handle");
+    let peer = match &*object.state.lock().unwrap() {
+        ObjectState::MessagePipe(mp) => mp.peer_native,
+        _ => unreachable!("not a message pipe endpoint"),
+    };
+    if peer == 0 {
+        return MojoResult::FailedPrecondition;
+    }
+    let Some(peer_object) = get_object(peer) else {
+        return MojoResult::FailedPrecondition;
+    };
+    match &mut *peer_object.state.lock().unwrap() {
+        ObjectState::MessagePipe(peer_mp) => peer_mp.incoming.push_back((bytes, handles)),
+        _ => unreachable!("peer is not a message pipe endpoint"),
+    }
+    peer_object.cond.notify_all();
+    notify_triggers(peer);
+    MojoResult::Okay
+}
+
+pub(crate) fn mp_read(native: u32) -> Result<(Vec<u8>, Vec<u32>), MojoResult> {
+    let object = get_object(native).expect("read() on an invalid handle");
+    match &mut *object.state.lock().unwrap() {
+        ObjectState::MessagePipe(mp) => match mp.incoming.pop_front() {
+            Some(message) => Ok(message),
+            None if mp.peer_native == 0 => Err(MojoResult::FailedPrecondition),
+            None => Err(MojoResult::ShouldWait),
+        },
+        _ => unreachable!("not a message pipe endpoint"),
+    }
+}
+
+// ******************** Data pipes ******************** //
+
+pub(crate) fn create_data_pipe(capacity: usize) -> (u32, u32) {
+    let consumer = next_handle();
+    let producer = next_handle();
+    let object = Arc::new(Object {
+        cond: Condvar::new(),
+        state: Mutex::new(ObjectState::DataPipe(DataPipeState {
+            producer_native: producer,
+            consumer_native: consumer,
+            ring: VecDeque::new(),
+            capacity,
+            write_in_progress: false,
+            read_in_progress: false,
+        })),
+    });
+    insert_object(consumer, object.clone());
+    insert_object(producer, object);
+    (consumer, producer)
+}
+
+fn with_data_pipe<R>(native: u32, f: impl FnOnce(&mut DataPipeState) -> R) -> R {
+    let object = get_object(native).expect("data pipe op on an invalid handle");
+    let mut state = object.state.lock().unwrap();
+    let result = match &mut *state {
+        ObjectState::DataPipe(dp) => f(dp),
+        _ => unreachable!("not a data pipe endpoint"),
+    };
+    drop(state);
+    object.cond.notify_all();
+    result
+}
+
+pub(crate) fn dp_write(native: u32, bytes: &[u8]) -> Result<usize, MojoResult> {
+    let (result, producer, consumer) = with_data_pipe(native, |dp| {
+        if dp.consumer_native == 0 {
+            return (Err(MojoResult::FailedPrecondition), dp.producer_native, dp.consumer_native);
+        }
+        if dp.write_in_progress {
+            return (Err(MojoResult::Busy), dp.producer_native, dp.consumer_native);
+        }
+        let available = dp.capacity.saturating_sub(dp.ring.len());
+        if available == 0 {
+            return (Err(MojoResult::ShouldWait), dp.producer_native, dp.consumer_native);
+        }
+        let n = bytes.len().min(available);
+        dp.ring.extend(bytes[..n].iter().copied());
+        (Ok(n), dp.producer_native, dp.consumer_native)
+    });
+    notify_triggers(producer);
+    notify_triggers(consumer);
+    result
+}
+
+pub(crate) fn dp_read(native: u32, discard: bool) -> Result<Vec<u8>, MojoResult> {
+    let (result, producer, consumer) = with_data_pipe(native, |dp| {
+        if dp.read_in_progress {
+            return (Err(MojoResult::Busy), dp.producer_native, dp.consumer_native);
+        }
+        if dp.ring.is_empty() {
+            let err = if dp.producer_native == 0 {
+                MojoResult::FailedPrecondition
+            } else {
+                MojoResult::ShouldWait
+            };
+            return (Err(err), dp.producer_native, dp.consumer_native);
+        }
+        let data: Vec<u8> = dp.ring.drain(..).collect();
+        (Ok(if discard { Vec::new() } else { data }), dp.producer_native, dp.consumer_native)
+    });
+    notify_triggers(producer);
+    notify_triggers(consumer);
+    result
+}
+
+pub(crate) fn dp_begin_write(native: u32) -> Result<Vec<MaybeUninit<u8>>, MojoResult> {
+    with_data_pipe(native, |dp| {
+        if dp.consumer_native == 0 {
+            return Err(MojoResult::FailedPrecondition);
+        }
+        if dp.write_in_progress {
+            return Err(MojoResult::Busy);
+        }
+        let available = dp.capacity.saturating_sub(dp.ring.len());
+        if available == 0 {
+            return Err(MojoResult::ShouldWait);
+        }
+        dp.write_in_progress = true;
+        Ok(vec![MaybeUninit::uninit(); available])
+    })
+}
+
+/// # Safety
+/// The first `committed` elements of `buf` must be initialized.
+pub(crate) unsafe fn dp_commit_write(native: u32, buf: Vec<MaybeUninit<u8>>, committed: usize) {
+    let (producer, consumer) = with_data_pipe(native, |dp| {
+        dp.write_in_progress = false;
+        let committed = committed.min(buf.len());
+        dp.ring.extend(buf[..committed].iter().map(|b| b.assume_init()));
+        (dp.producer_native, dp.consumer_native)
+    });
+    notify_triggers(producer);
+    notify_triggers(consumer);
+}
+
+pub(crate) fn dp_begin_read(native: u32) -> Result<Vec<u8>, MojoResult> {
+    with_data_pipe(native, |dp| {
+        if dp.read_in_progress {
+            return Err(MojoResult::Busy);
+        }
+        if dp.ring.is_empty() {
+            let err = if dp.producer_native == 0 {
+                MojoResult::FailedPrecondition
+            } else {
+                MojoResult::ShouldWait
+            };
+            return Err(err);
+        }
+        dp.read_in_progress = true;
+        Ok(dp.ring.iter().copied().collect())
+    })
+}
+
+pub(crate) fn dp_commit_read(native: u32, committed: usize) {
+    let (producer, consumer) = with_data_pipe(native, |dp| {
+        dp.read_in_progress = false;
+        let n = committed.min(dp.ring.len());
+        dp.ring.drain(..n);
+        (dp.producer_native, dp.consumer_native)
+    });
+    notify_triggers(producer);
+    notify_triggers(consumer);
+}
+
+// ******************** Shared buffers ******************** //
+
+pub(crate) fn create_shared_buffer(num_bytes: u64) -> u32 {
+    let native = next_handle();
+    insert_object(
+        native,
+        Arc::new(Object {
+            cond: Condvar::new(),
+            state: Mutex::new(ObjectState::SharedBuffer(Arc::new(Mutex::new(vec![
+                0u8;
+                num_bytes
+                    as usize
+            ])))),
+        }),
+    );
+    native
+}
+
+pub(crate) fn sb_buffer(native: u32) -> Arc<Mutex<Vec<u8>>> {
+    let object = get_object(native).expect("shared buffer op on an invalid handle");
+    match &*object.state.lock().unwrap() {
+        ObjectState::SharedBuffer(buf) => buf.clone(),
+        _ => unreachable!("not a shared buffer"),
+    }
+}
+
+pub(crate) fn sb_duplicate(native: u32) -> u32 {
+    let buf = sb_buffer(native);
+    let new_native = next_handle();
+    insert_object(
+        new_native,
+        Arc::new(Object { cond: Condvar::new(), state: Mutex::new(ObjectState::SharedBuffer(buf)) }),
+    );
+    new_native
+}
+
+// ******************** Traps ******************** //
+
+struct TriggerEntry {
+    id: u64,
+    native: u32,
+    signals: HandleSignals,
+    condition: TriggerCondition,
+    trap: Arc<TrapState>,
+    fire: Box<dyn Fn(MojoResult, SignalsState) + Send>,
+}
+
+/// Shared state for a `Trap`/`UnsafeTrap`: whether it's currently armed
+/// (live-watching its triggers) and which trigger ids belong to it, so
+/// closing the trap can cancel whatever's left.
+pub(crate) struct TrapState {
+    pub(crate) armed: Mutex<bool>,
+    trigger_ids: Mutex<Vec<u64>>,
+}
+
+static NEXT_TRIGGER_ID: AtomicU64 = AtomicU64::new(1);
+
+lazy_static::lazy_static! {
+    static ref TRIGGERS: Mutex<Vec<TriggerEntry>> = Mutex::new(Vec::new());
+}
+
+pub(crate) fn trap_new() -> Arc<TrapState> {
+    Arc::new(TrapState { armed: Mutex::new(false), trigger_ids: Mutex::new(Vec::new()) })
+}
+
+pub(crate) fn trap_add_trigger(
+    trap: &Arc<TrapState>,
+    native: u32,
+    signals: HandleSignals,
+    condition: TriggerCondition,
+    fire: Box<dyn Fn(MojoResult, SignalsState) + Send>,
+) -> (MojoResult, u64) {
+    if get_object(native).is_none() {
+        return (MojoResult::InvalidArgument, 0);
+    }
+    let id = NEXT_TRIGGER_ID.fetch_add(1, Ordering::Relaxed);
+    TRIGGERS.lock().unwrap().push(TriggerEntry {
+        id,
+        native,
+        signals,
+        condition,
+        trap: trap.clone(),
+        fire,
+    });
+    trap.trigger_ids.lock().unwrap().push(id);
+    (MojoResult::Okay, id)
+}
+
+/// Removes a single trigger by id without firing any event for it, e.g.
+/// because the [`crate::system::trap::TriggerToken`] guarding it was
+/// dropped. Unlike [`cancel_triggers_for_handle`] and [`trap_close`], this
+/// isn't a signal-state transition, so no handler runs.
+pub(crate) fn trap_remove_trigger(trap: &Arc<TrapState>, id: u64) {
+    trap.trigger_ids.lock().unwrap().retain(|existing| *existing != id);
+    TRIGGERS.lock().unwrap().retain(|entry| entry.id != id);
+}
+
+fn evaluate(condition: TriggerCondition, watched: HandleSignals, cur: SignalsState) -> Option<(MojoResult, bool)> {
+    match condition {
+        TriggerCondition::SignalsSatisfied => {
+            if cur.satisfied().contains(watched) {
+                Some((MojoResult::Okay, false))
+            } else if !cur.satisfiable().contains(watched) {
+                // Not terminal: the trigger stays registered (its trap just
+                // disarms) until its handle or trap closes and cancels it.
+                Some((MojoResult::FailedPrecondition, false))
+            } else {
+                None
+            }
+        }
+        TriggerCondition::SignalsUnsatisfied => {
+            if !cur.satisfied().contains(watched) {
+                Some((MojoResult::Okay, false))
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// A trigger whose condition already holds at the moment `arm()` was called.
+pub(crate) struct ReadyTrigger {
+    pub(crate) native: u32,
+    pub(crate) result: MojoResult,
+    pub(crate) signals_state: SignalsState,
+}
+
+/// Attempts to arm `trap`. If every trigger's condition is currently false
+/// (but could still become true), the trap becomes armed and this returns
+/// an empty list. Otherwise the trap stays disarmed and this returns every
+/// trigger that's already ready (removing any whose result is terminal).
+///
+/// When `invoke` is true, each ready trigger's handler is called directly
+/// (matching the native API's behavior when no blocking-events buffer is
+/// supplied to `MojoTrapArm`); when false, the caller is expected to report
+/// the ready events itself, e.g. into a buffer.
+pub(crate) fn trap_arm(trap: &Arc<TrapState>, invoke: bool) -> Vec<ReadyTrigger> {
+    let ids = trap.trigger_ids.lock().unwrap().clone();
+    let mut ready = Vec::new();
+    let mut terminal_ids = Vec::new();
+    {
+        let table = TRIGGERS.lock().unwrap();
+        for entry in table.iter().filter(|e| ids.contains(&e.id)) {
+            let cur = signals(entry.native);
+            if let Some((result, terminal)) = evaluate(entry.condition, entry.signals, cur) {
+                if invoke {
+                    (entry.fire)(result, cur);
+                }
+                ready.push(ReadyTrigger { native: entry.native, result, signals_state: cur });
+                if terminal {
+                    terminal_ids.push(entry.id);
+                }
+            }
+        }
+    }
+    if ready.is_empty() {
+        *trap.armed.lock().unwrap() = true;
+    } else {
+        *trap.armed.lock().unwrap() = false;
+        if !terminal_ids.is_empty() {
+            TRIGGERS.lock().unwrap().retain(|e| !terminal_ids.contains(&e.id));
+            trap.trigger_ids.lock().unwrap().retain(|id| !terminal_ids.contains(id));
+        }
+    }
+    ready
+}
+
+/// Called after any mutation that could change `native`'s signals state:
+/// fires (and, if terminal, removes) any trigger on `native` whose trap is
+/// currently armed.
+fn notify_triggers(native: u32) {
+    if native == 0 {
+        return;
+    }
+    let cur = signals(native);
+    let mut remove_ids = Vec::new();
+    {
+        let table = TRIGGERS.lock().unwrap();
+        for entry in table.iter().filter(|e| e.native == native) {
+            let mut armed = entry.trap.armed.lock().unwrap();
+            if !*armed {
+                continue;
+            }
+            if let Some((result, terminal)) = evaluate(entry.condition, entry.signals, cur) {
+                *armed = false;
+                drop(armed);
+                (entry.fire)(result, cur);
+                if terminal {
+                    remove_ids.push(entry.id);
+                }
+            }
+        }
+    }
+    if !remove_ids.is_empty() {
+        TRIGGERS.lock().unwrap().retain(|e| !remove_ids.contains(&e.id));
+    }
+}
+
+/// Fires `Cancelled` for, and removes, every trigger registered on `native`
+/// (regardless of whether its trap is armed). Called when `native` closes.
+fn cancel_triggers_for_handle(native: u32) {
+    let removed: Vec<TriggerEntry> = {
+        let mut table = TRIGGERS.lock().unwrap();
+        let (removed, kept): (Vec<_>, Vec<_>) =
+            std::mem::take(&mut *table).into_iter().partition(|e| e.native == native);
+        *table = kept;
+        removed
+    };
+    for entry in &removed {
+        entry.trap.trigger_ids.lock().unwrap().retain(|id| *id != entry.id);
+        (entry.fire)(MojoResult::Cancelled, SignalsState::default());
+    }
+}
+
+/// Cancels and removes every trigger still registered on `trap`, e.g.
+/// because the `Trap`/`UnsafeTrap` itself is being dropped.
+pub(crate) fn trap_close(trap: &Arc<TrapState>) {
+    let ids = std::mem::take(&mut *trap.trigger_ids.lock().unwrap());
+    let removed: Vec<TriggerEntry> = {
+        let mut table = TRIGGERS.lock().unwrap();
+        let (removed, kept): (Vec<_>, Vec<_>) =
+            std::mem::take(&mut *table).into_iter().partition(|e| ids.contains(&e.id));
+        *table = kept;
+        removed
+    };
+    for entry in removed {
+        (entry.fire)(MojoResult::Cancelled, SignalsState::default());
+    }
+}
@@ -0,0 +1,229 @@
+// Copyright 2024 The Chromium Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use crate::system::MojoResult;
+
+#[cfg(feature = "emulate")]
+use crate::system::emulate;
+#[cfg(not(feature = "emulate"))]
+use crate::system::native;
+
+bitflags::bitflags! {
+    /// The set of signals that may be waited on for a Mojo handle.
+    #[derive(Default)]
+    pub struct HandleSignals: u32 {
+        const NONE = 0;
+        const READABLE = 1 << 0;
+        const WRITABLE = 1 << 1;
+        const PEER_CLOSED = 1 << 2;
+        const QUOTA_EXCEEDED = 1 << 3;
+    }
+}
+
+/// The satisfied and satisfiable signals for a handle at some point in time.
+///
+/// `satisfied` is the subset of signals currently true; `satisfiable` is the
+/// subset of signals that could ever become true in the future. A signal
+/// that is satisfied is always also satisfiable.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SignalsState {
+    satisfied: HandleSignals,
+    satisfiable: HandleSignals,
+}
+
+impl SignalsState {
+    pub fn new(satisfied: HandleSignals, satisfiable: HandleSignals) -> SignalsState {
+        SignalsState { satisfied, satisfiable }
+    }
+
+    pub fn satisfied(&self) -> HandleSignals {
+        self.satisfied
+    }
+
+    pub fn satisfiable(&self) -> HandleSignals {
+        self.satisfiable
+    }
+}
+
+impl HandleSignals {
+    pub fn is_readable(&self) -> bool {
+        self.contains(HandleSignals::READABLE)
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.contains(HandleSignals::WRITABLE)
+    }
+
+    pub fn is_peer_closed(&self) -> bool {
+        self.contains(HandleSignals::PEER_CLOSED)
+    }
+}
+
+/// The pending result of a call to [`Handle::wait`].
+///
+/// Waiting does not immediately say whether the requested signals were
+/// satisfied; a caller must say which outcome they're waiting for by calling
+/// [`satisfied`](WaitFuture::satisfied) or
+/// [`unsatisfiable`](WaitFuture::unsatisfiable), which blocks until that
+/// outcome (or its opposite) occurs.
+#[must_use]
+pub struct WaitFuture {
+    native_handle: u32,
+    signals: HandleSignals,
+}
+
+impl WaitFuture {
+    /// Blocks until every requested signal is satisfied, returning the
+    /// resulting state, or until the requested signals can no longer all be
+    /// satisfied, in which case this returns `Err(MojoResult::FailedPrecondition)`.
+    pub fn satisfied(self) -> Result<SignalsState, MojoResult> {
+        let state = block_until_settled(self.native_handle, self.signals);
+        if state.satisfied().contains(self.signals) {
+            Ok(state)
+        } else {
+            Err(MojoResult::FailedPrecondition)
+        }
+    }
+
+    /// Blocks until the requested signals can no longer all be satisfied,
+    /// returning the resulting state, or until they're all actually
+    /// satisfied, in which case this returns
+    /// `Err(MojoResult::FailedPrecondition)`.
+    pub fn unsatisfiable(self) -> Result<SignalsState, MojoResult> {
+        let state = block_until_settled(self.native_handle, self.signals);
+        if !state.satisfiable().contains(self.signals) {
+            Ok(state)
+        } else {
+            Err(MojoResult::FailedPrecondition)
+        }
+    }
+}
+
+#[cfg(feature = "emulate")]
+fn block_until_settled(native_handle: u32, signals: HandleSignals) -> SignalsState {
+    emulate::wait(native_handle, signals)
+}
+
+#[cfg(not(feature = "emulate"))]
+fn block_until_settled(native_handle: u32, signals: HandleSignals) -> SignalsState {
+    native::wait(native_handle, signals)
+}
+
+/// A Mojo handle: a reference-counted capability to some system resource
+/// (a message pipe endpoint, a data pipe endpoint, a shared buffer, ...).
+pub trait Handle: Sized {
+    /// Returns the native (`u32`) representation of the handle, which is
+    /// valid as long as `self` has not been invalidated or dropped.
+    fn get_native_handle(&self) -> u32;
+
+    /// Returns whether this handle refers to a live resource.
+    fn is_valid(&self) -> bool {
+        self.get_native_handle() != 0
+    }
+
+    /// Invalidates this handle without closing the underlying resource,
+    /// e.g. because ownership of the native handle has been transferred
+    /// elsewhere.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure the underlying resource is closed by some
+    /// other means, or it will leak.
+    unsafe fn invalidate(&mut self);
+
+    /// Begins a wait for all of `signals` on this handle. See [`WaitFuture`]
+    /// for how to block on the result.
+    fn wait(&self, signals: HandleSignals) -> WaitFuture {
+        WaitFuture { native_handle: self.get_native_handle(), signals }
+    }
+
+    /// Like [`wait`](Handle::wait), but resolves `signals` via the async
+    /// reactor instead of blocking a thread. See
+    /// [`crate::system::reactor::wait_async`].
+    fn wait_async(&self, signals: HandleSignals) -> crate::system::reactor::WaitAsync {
+        crate::system::reactor::wait_async(self.get_native_handle(), signals)
+    }
+}
+
+/// Whatever implements this trait can be constructed from, and decomposed
+/// into, a type-erased [`UntypedHandle`].
+pub trait CastHandle: Handle {
+    /// Consumes this handle and returns the equivalent `UntypedHandle`.
+    fn as_untyped(self) -> UntypedHandle;
+
+    /// Assumes that `handle` refers to a resource of the correct underlying
+    /// kind and wraps it back up in `Self`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee `handle` actually refers to a resource of
+    /// kind `Self`.
+    unsafe fn from_untyped(handle: UntypedHandle) -> Self;
+}
+
+/// A Mojo handle of unknown kind.
+///
+/// Closes the underlying resource on drop, same as every other typed handle.
+#[derive(Debug)]
+pub struct UntypedHandle {
+    value: u32,
+}
+
+impl Handle for UntypedHandle {
+    fn get_native_handle(&self) -> u32 {
+        self.value
+    }
+
+    unsafe fn invalidate(&mut self) {
+        self.value = 0;
+    }
+}
+
+impl CastHandle for UntypedHandle {
+    fn as_untyped(self) -> UntypedHandle {
+        self
+    }
+
+    unsafe fn from_untyped(handle: UntypedHandle) -> Self {
+        handle
+    }
+}
+
+impl Drop for UntypedHandle {
+    fn drop(&mut self) {
+        if self.is_valid() {
+            close(self.value);
+        }
+    }
+}
+
+#[cfg(feature = "emulate")]
+fn close(native_handle: u32) {
+    emulate::close(native_handle);
+}
+
+#[cfg(not(feature = "emulate"))]
+fn close(native_handle: u32) {
+    native::close(native_handle);
+}
+
+/// Wraps a raw native handle value into an [`UntypedHandle`] without
+/// checking that it refers to a live resource.
+///
+/// # Safety
+///
+/// The caller must guarantee `native_handle` is either `0` (invalid) or a
+/// currently-live handle value that isn't owned elsewhere, since dropping
+/// the returned handle closes it.
+pub unsafe fn acquire(native_handle: u32) -> UntypedHandle {
+    UntypedHandle { value: native_handle }
+}
+
+/// Constructs a typed handle directly from its native value. Only visible
+/// within the crate: typed wrapper modules (`message_pipe`, `data_pipe`, ...)
+/// use this to hand back handles they've just created or looked up in the
+/// registry.
+pub(crate) fn wrap_native(native_handle: u32) -> UntypedHandle {
+    UntypedHandle { value: native_handle }
+}
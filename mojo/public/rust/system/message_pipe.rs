@@ -0,0 +1,161 @@
+// Copyright 2024 The Chromium Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Message pipes: a bidirectional, FIFO, byte-and-handle-carrying channel
+//! with two endpoints.
+
+use crate::system::handle;
+use crate::system::{CastHandle, Handle, HandleSignals, MojoResult, UntypedHandle};
+
+#[cfg(feature = "emulate")]
+use crate::system::emulate;
+#[cfg(not(feature = "emulate"))]
+use crate::system::native;
+
+/// One endpoint of a message pipe.
+#[derive(Debug)]
+pub struct MessageEndpoint {
+    handle: UntypedHandle,
+}
+
+/// Creates a new message pipe, returning its two endpoints.
+pub fn create() -> Result<(MessageEndpoint, MessageEndpoint), MojoResult> {
+    let (a, b) = create_native_pair();
+    Ok((MessageEndpoint { handle: handle::wrap_native(a) }, MessageEndpoint { handle: handle::wrap_native(b) }))
+}
+
+#[cfg(feature = "emulate")]
+fn create_native_pair() -> (u32, u32) {
+    emulate::create_message_pipe()
+}
+
+#[cfg(not(feature = "emulate"))]
+fn create_native_pair() -> (u32, u32) {
+    native::create_message_pipe()
+}
+
+impl MessageEndpoint {
+    /// Writes `bytes` and transfers ownership of `handles` to the peer
+    /// endpoint in a single message.
+    pub fn write(&self, bytes: &[u8], handles: Vec<u32>) -> MojoResult {
+        write(self.get_native_handle(), bytes.to_vec(), handles)
+    }
+
+    /// Reads the oldest unread message, or `Err(MojoResult::ShouldWait)` if
+    /// none is available yet, or `Err(MojoResult::FailedPrecondition)` if the
+    /// peer has closed and no messages remain.
+    pub fn read(&self) -> Result<(Vec<u8>, Vec<u32>), MojoResult> {
+        read(self.get_native_handle())
+    }
+}
+
+#[cfg(feature = "emulate")]
+fn write(native: u32, bytes: Vec<u8>, handles: Vec<u32>) -> MojoResult {
+    emulate::mp_write(native, bytes, handles)
+}
+
+#[cfg(not(feature = "emulate"))]
+fn write(native: u32, bytes: Vec<u8>, handles: Vec<u32>) -> MojoResult {
+    native::mp_write(native, bytes, handles)
+}
+
+#[cfg(feature = "emulate")]
+fn read(native: u32) -> Result<(Vec<u8>, Vec<u32>), MojoResult> {
+    emulate::mp_read(native)
+}
+
+#[cfg(not(feature = "emulate"))]
+fn read(native: u32) -> Result<(Vec<u8>, Vec<u32>), MojoResult> {
+    native::mp_read(native)
+}
+
+impl Handle for MessageEndpoint {
+    fn get_native_handle(&self) -> u32 {
+        self.handle.get_native_handle()
+    }
+
+    unsafe fn invalidate(&mut self) {
+        self.handle.invalidate()
+    }
+}
+
+impl CastHandle for MessageEndpoint {
+    fn as_untyped(self) -> UntypedHandle {
+        self.handle
+    }
+
+    unsafe fn from_untyped(handle: UntypedHandle) -> Self {
+        MessageEndpoint { handle }
+    }
+}
+
+/// An async-friendly wrapper around a [`MessageEndpoint`], exposing
+/// `.await`-able [`send`](AsyncMessageEndpoint::send) and
+/// [`recv`](AsyncMessageEndpoint::recv) built on the
+/// [`crate::system::reactor`] instead of blocking on
+/// [`Handle::wait`].
+pub struct AsyncMessageEndpoint {
+    inner: MessageEndpoint,
+}
+
+impl AsyncMessageEndpoint {
+    pub fn new(inner: MessageEndpoint) -> AsyncMessageEndpoint {
+        AsyncMessageEndpoint { inner }
+    }
+
+    /// Sends a message, transferring ownership of `handles` to the peer.
+    pub async fn send(&self, bytes: &[u8], handles: Vec<UntypedHandle>) -> Result<(), MojoResult> {
+        let natives = handles
+            .into_iter()
+            .map(|handle| {
+                let native = handle.get_native_handle();
+                // The message now owns this handle; don't close it here.
+                std::mem::forget(handle);
+                native
+            })
+            .collect();
+        match self.inner.write(bytes, natives) {
+            MojoResult::Okay => Ok(()),
+            other => Err(other),
+        }
+    }
+
+    /// Waits for and reads the next message. Once the peer has closed and no
+    /// messages remain, returns `Err(MojoResult::FailedPrecondition)`.
+    pub async fn recv(&self) -> Result<(Vec<u8>, Vec<UntypedHandle>), MojoResult> {
+        loop {
+            match self.inner.read() {
+                Ok((bytes, natives)) => {
+                    let handles =
+                        natives.into_iter().map(|native| unsafe { handle::acquire(native) }).collect();
+                    return Ok((bytes, handles));
+                }
+                Err(MojoResult::ShouldWait) => {
+                    self.inner.wait_async(HandleSignals::READABLE).await?;
+                }
+                Err(other) => return Err(other),
+            }
+        }
+    }
+}
+
+impl Handle for AsyncMessageEndpoint {
+    fn get_native_handle(&self) -> u32 {
+        self.inner.get_native_handle()
+    }
+
+    unsafe fn invalidate(&mut self) {
+        self.inner.invalidate()
+    }
+}
+
+impl CastHandle for AsyncMessageEndpoint {
+    fn as_untyped(self) -> UntypedHandle {
+        self.inner.as_untyped()
+    }
+
+    unsafe fn from_untyped(handle: UntypedHandle) -> Self {
+        AsyncMessageEndpoint { inner: MessageEndpoint::from_untyped(handle) }
+    }
+}
@@ -0,0 +1,82 @@
+// Copyright 2024 The Chromium Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! The `system` package wraps the low-level Mojo system APIs: handles,
+//! message pipes, data pipes, shared buffers, traps, and wait sets.
+//!
+//! By default this crate talks to the native Mojo EDK through FFI. Building
+//! with the `emulate` feature swaps in a pure-Rust, in-process backend (see
+//! the [`emulate`] module) that implements the same handle semantics without
+//! linking the native library, so this surface and anything built on top of
+//! it (e.g. the `bindings` crate) can run on any host.
+
+#[cfg(feature = "emulate")]
+pub(crate) mod emulate;
+
+#[cfg(not(feature = "emulate"))]
+mod native;
+
+pub mod data_pipe;
+mod handle;
+pub mod message_pipe;
+pub mod reactor;
+pub mod shared_buffer;
+pub mod trap;
+pub mod wait_set;
+
+pub use handle::{acquire, CastHandle, Handle, HandleSignals, SignalsState, UntypedHandle};
+
+/// The result of a Mojo system call.
+///
+/// Mirrors `MojoResult` from `mojo/public/c/system/types.h`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(i32)]
+pub enum MojoResult {
+    Okay = 0,
+    Cancelled = 1,
+    Unknown = 2,
+    InvalidArgument = 3,
+    DeadlineExceeded = 4,
+    NotFound = 5,
+    AlreadyExists = 6,
+    PermissionDenied = 7,
+    ResourceExhausted = 8,
+    FailedPrecondition = 9,
+    Aborted = 10,
+    OutOfRange = 11,
+    Unimplemented = 12,
+    Internal = 13,
+    Unavailable = 14,
+    DataLoss = 15,
+    Busy = 16,
+    ShouldWait = 17,
+}
+
+impl MojoResult {
+    /// Converts a raw `MojoResult` code as returned by the native API (or
+    /// synthesized by the emulated backend) into its Rust enum form.
+    pub(crate) fn from_code(code: i32) -> MojoResult {
+        match code {
+            0 => MojoResult::Okay,
+            1 => MojoResult::Cancelled,
+            2 => MojoResult::Unknown,
+            3 => MojoResult::InvalidArgument,
+            4 => MojoResult::DeadlineExceeded,
+            5 => MojoResult::NotFound,
+            6 => MojoResult::AlreadyExists,
+            7 => MojoResult::PermissionDenied,
+            8 => MojoResult::ResourceExhausted,
+            9 => MojoResult::FailedPrecondition,
+            10 => MojoResult::Aborted,
+            11 => MojoResult::OutOfRange,
+            12 => MojoResult::Unimplemented,
+            13 => MojoResult::Internal,
+            14 => MojoResult::Unavailable,
+            15 => MojoResult::DataLoss,
+            16 => MojoResult::Busy,
+            17 => MojoResult::ShouldWait,
+            other => panic!("unrecognized MojoResult code: {}", other),
+        }
+    }
+}
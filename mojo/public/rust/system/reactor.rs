@@ -0,0 +1,109 @@
+// Copyright 2024 The Chromium Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! An async reactor built on top of [`crate::system::trap::Trap`], letting
+//! callers `.await` handle readiness instead of blocking a thread on
+//! [`crate::system::Handle::wait`].
+//!
+//! A single process-global [`Trap`] is shared by every [`wait_async`] call.
+//! Each call reserves a slot in a [`Slab`] keyed by a `u64` cookie, which
+//! doubles as the trigger's context: the trap's handler (which may run on a
+//! foreign Mojo notification thread, so it must never panic) looks the
+//! cookie up, stashes the result, and wakes whatever task is waiting on it.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+use slab::Slab;
+
+use crate::system::trap::{Trap, TrapEvent, TriggerCondition, TriggerToken};
+use crate::system::{HandleSignals, MojoResult, SignalsState};
+
+struct Slot {
+    waker: Option<Waker>,
+    result: Option<Result<SignalsState, MojoResult>>,
+}
+
+lazy_static::lazy_static! {
+    static ref SLOTS: std::sync::Mutex<Slab<Slot>> = std::sync::Mutex::new(Slab::new());
+    static ref REACTOR: Trap<u64> =
+        Trap::new(on_trap_event).expect("failed to create the async reactor's trap");
+}
+
+fn on_trap_event(event: &TrapEvent, cookie: &u64) {
+    let mut slots = SLOTS.lock().unwrap();
+    let Some(slot) = slots.get_mut(*cookie as usize) else { return };
+    slot.result = Some(match event.result() {
+        MojoResult::Okay => Ok(event.signals_state()),
+        other => Err(other),
+    });
+    if let Some(waker) = slot.waker.take() {
+        waker.wake();
+    }
+}
+
+/// Waits for all of `signals` to become satisfied on `native_handle`,
+/// without blocking a thread. Resolves to `Err` if the requested signals can
+/// never be satisfied (e.g. the peer closed) or if the handle closes while
+/// the wait is pending.
+pub fn wait_async(native_handle: u32, signals: HandleSignals) -> WaitAsync {
+    WaitAsync { native_handle, signals, cookie: None, _token: None }
+}
+
+/// A future returned by [`wait_async`]. See the module documentation for how
+/// it's driven by the shared reactor [`Trap`].
+pub struct WaitAsync {
+    native_handle: u32,
+    signals: HandleSignals,
+    cookie: Option<u64>,
+    _token: Option<TriggerToken>,
+}
+
+impl Future for WaitAsync {
+    type Output = Result<SignalsState, MojoResult>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let cookie = match self.cookie {
+            Some(cookie) => cookie,
+            None => {
+                let cookie =
+                    SLOTS.lock().unwrap().insert(Slot { waker: Some(cx.waker().clone()), result: None })
+                        as u64;
+                self.cookie = Some(cookie);
+                // Arming may synchronously invoke `on_trap_event` above if
+                // the signals are already satisfied (or never will be), in
+                // which case the slot's result is already filled in by the
+                // time we check it below.
+                let token =
+                    REACTOR.add_trigger(self.native_handle, self.signals, TriggerCondition::SignalsSatisfied, cookie);
+                self._token = Some(token);
+                REACTOR.arm();
+                cookie
+            }
+        };
+        let mut slots = SLOTS.lock().unwrap();
+        let slot = slots.get_mut(cookie as usize).expect("wait_async cookie missing from the reactor slab");
+        match slot.result.take() {
+            Some(result) => {
+                slots.remove(cookie as usize);
+                drop(slots);
+                self._token = None;
+                Poll::Ready(result)
+            }
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for WaitAsync {
+    fn drop(&mut self) {
+        if let Some(cookie) = self.cookie.take() {
+            SLOTS.lock().unwrap().try_remove(cookie as usize);
+        }
+    }
+}
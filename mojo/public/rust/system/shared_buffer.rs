@@ -0,0 +1,144 @@
+// Copyright 2024 The Chromium Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Shared buffers: a block of memory that can be mapped into this process
+//! (possibly more than once, and possibly after being duplicated into
+//! another handle that refers to the same underlying memory).
+
+use std::sync::{Arc, Mutex};
+
+use crate::system::handle;
+use crate::system::{CastHandle, Handle, MojoResult, UntypedHandle};
+
+#[cfg(feature = "emulate")]
+use crate::system::emulate;
+#[cfg(not(feature = "emulate"))]
+use crate::system::native;
+
+bitflags::bitflags! {
+    /// Flags accepted by [`SharedBuffer::duplicate`].
+    #[derive(Default)]
+    pub struct DuplicateFlags: u32 {
+        const NONE = 0;
+    }
+}
+
+bitflags::bitflags! {
+    /// Flags accepted by [`SharedBuffer::map`].
+    #[derive(Default)]
+    pub struct MapFlags: u32 {
+        const NONE = 0;
+    }
+}
+
+/// A handle to a block of shared memory.
+#[derive(Debug)]
+pub struct SharedBuffer {
+    handle: UntypedHandle,
+}
+
+impl SharedBuffer {
+    /// Creates a new shared buffer of `num_bytes` bytes.
+    pub fn new(num_bytes: u64) -> Result<SharedBuffer, MojoResult> {
+        let native = create(num_bytes);
+        Ok(SharedBuffer { handle: handle::wrap_native(native) })
+    }
+
+    /// Returns the size, in bytes, of this buffer.
+    pub fn get_info(&self) -> Result<u64, MojoResult> {
+        Ok(buffer(self.get_native_handle()).lock().unwrap().len() as u64)
+    }
+
+    /// Maps `len` bytes starting at `offset` into this process's address
+    /// space.
+    pub fn map(&self, offset: u64, len: u64) -> Result<MappedBuffer, MojoResult> {
+        Ok(MappedBuffer { buffer: buffer(self.get_native_handle()), offset: offset as usize, len: len as usize })
+    }
+
+    /// Creates a new handle referring to the same underlying memory as this
+    /// one.
+    pub fn duplicate(&self, _flags: DuplicateFlags) -> Result<SharedBuffer, MojoResult> {
+        let native = duplicate(self.get_native_handle());
+        Ok(SharedBuffer { handle: handle::wrap_native(native) })
+    }
+}
+
+#[cfg(feature = "emulate")]
+fn create(num_bytes: u64) -> u32 {
+    emulate::create_shared_buffer(num_bytes)
+}
+
+#[cfg(not(feature = "emulate"))]
+fn create(num_bytes: u64) -> u32 {
+    native::create_shared_buffer(num_bytes)
+}
+
+#[cfg(feature = "emulate")]
+fn buffer(native: u32) -> Arc<Mutex<Vec<u8>>> {
+    emulate::sb_buffer(native)
+}
+
+#[cfg(not(feature = "emulate"))]
+fn buffer(native: u32) -> Arc<Mutex<Vec<u8>>> {
+    native::sb_buffer(native)
+}
+
+#[cfg(feature = "emulate")]
+fn duplicate(native: u32) -> u32 {
+    emulate::sb_duplicate(native)
+}
+
+#[cfg(not(feature = "emulate"))]
+fn duplicate(native: u32) -> u32 {
+    native::sb_duplicate(native)
+}
+
+impl Handle for SharedBuffer {
+    fn get_native_handle(&self) -> u32 {
+        self.handle.get_native_handle()
+    }
+
+    unsafe fn invalidate(&mut self) {
+        self.handle.invalidate()
+    }
+}
+
+impl CastHandle for SharedBuffer {
+    fn as_untyped(self) -> UntypedHandle {
+        self.handle
+    }
+
+    unsafe fn from_untyped(handle: UntypedHandle) -> Self {
+        SharedBuffer { handle }
+    }
+}
+
+/// A view of a [`SharedBuffer`]'s memory mapped into this process. Unmapped
+/// on drop; the underlying memory itself lives as long as some handle to it
+/// does.
+pub struct MappedBuffer {
+    buffer: Arc<Mutex<Vec<u8>>>,
+    offset: usize,
+    len: usize,
+}
+
+impl MappedBuffer {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Reads the byte at `index` within this mapping.
+    pub fn read(&self, index: usize) -> u8 {
+        self.buffer.lock().unwrap()[self.offset + index]
+    }
+
+    /// Writes `value` at `index` within this mapping.
+    pub fn write(&mut self, index: usize, value: u8) {
+        self.buffer.lock().unwrap()[self.offset + index] = value;
+    }
+}
@@ -0,0 +1,319 @@
+// Copyright 2024 The Chromium Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Traps let a caller be notified when a handle's signals change, without
+//! blocking a thread the way [`crate::system::Handle::wait`] does.
+//!
+//! [`UnsafeTrap`] mirrors the native Mojo C API directly: its handler is an
+//! `extern "C" fn` invoked from a foreign notification thread, so it must be
+//! `Send`-safe and must never panic. [`Trap`] is a safe wrapper that lets the
+//! handler be an arbitrary Rust closure paired with a context value of the
+//! caller's choosing.
+
+use std::mem::MaybeUninit;
+use std::sync::Arc;
+
+#[cfg(feature = "emulate")]
+use crate::system::emulate;
+#[cfg(not(feature = "emulate"))]
+use crate::system::native;
+
+use crate::system::handle::{HandleSignals, SignalsState};
+use crate::system::MojoResult;
+
+/// The backend-specific state backing a [`Trap`]/[`UnsafeTrap`]. Opaque to
+/// this file; only ever passed around as `Arc<TrapState>` and handed to the
+/// `trap_*` functions below.
+#[cfg(feature = "emulate")]
+type TrapState = emulate::TrapState;
+#[cfg(not(feature = "emulate"))]
+type TrapState = native::TrapState;
+
+#[cfg(feature = "emulate")]
+fn trap_new() -> Arc<TrapState> {
+    emulate::trap_new()
+}
+
+#[cfg(not(feature = "emulate"))]
+fn trap_new() -> Arc<TrapState> {
+    native::trap_new()
+}
+
+#[cfg(feature = "emulate")]
+fn trap_add_trigger(
+    trap: &Arc<TrapState>,
+    native_handle: u32,
+    signals: HandleSignals,
+    condition: TriggerCondition,
+    fire: Box<dyn Fn(MojoResult, SignalsState) + Send>,
+) -> (MojoResult, u64) {
+    emulate::trap_add_trigger(trap, native_handle, signals, condition, fire)
+}
+
+#[cfg(not(feature = "emulate"))]
+fn trap_add_trigger(
+    trap: &Arc<TrapState>,
+    native_handle: u32,
+    signals: HandleSignals,
+    condition: TriggerCondition,
+    fire: Box<dyn Fn(MojoResult, SignalsState) + Send>,
+) -> (MojoResult, u64) {
+    native::trap_add_trigger(trap, native_handle, signals, condition, fire)
+}
+
+#[cfg(feature = "emulate")]
+fn trap_remove_trigger(trap: &Arc<TrapState>, id: u64) {
+    emulate::trap_remove_trigger(trap, id)
+}
+
+#[cfg(not(feature = "emulate"))]
+fn trap_remove_trigger(trap: &Arc<TrapState>, id: u64) {
+    native::trap_remove_trigger(trap, id)
+}
+
+/// A trigger whose condition already held at the moment `trap_arm` was
+/// called; see `result`/`signals_state` field access below. Opaque to this
+/// file beyond that.
+#[cfg(feature = "emulate")]
+type ReadyTrigger = emulate::ReadyTrigger;
+#[cfg(not(feature = "emulate"))]
+type ReadyTrigger = native::ReadyTrigger;
+
+#[cfg(feature = "emulate")]
+fn trap_arm(trap: &Arc<TrapState>, invoke: bool) -> Vec<ReadyTrigger> {
+    emulate::trap_arm(trap, invoke)
+}
+
+#[cfg(not(feature = "emulate"))]
+fn trap_arm(trap: &Arc<TrapState>, invoke: bool) -> Vec<ReadyTrigger> {
+    native::trap_arm(trap, invoke)
+}
+
+#[cfg(feature = "emulate")]
+fn trap_close(trap: &Arc<TrapState>) {
+    emulate::trap_close(trap)
+}
+
+#[cfg(not(feature = "emulate"))]
+fn trap_close(trap: &Arc<TrapState>) {
+    native::trap_close(trap)
+}
+
+/// The condition under which a trigger fires.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TriggerCondition {
+    /// Fire once every requested signal is satisfied.
+    SignalsSatisfied,
+    /// Fire once any requested signal is no longer satisfied.
+    SignalsUnsatisfied,
+}
+
+/// The event delivered to an [`UnsafeTrap`]'s handler.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct UnsafeTrapEvent {
+    trigger_context: u64,
+    result: i32,
+    satisfied: u32,
+    satisfiable: u32,
+}
+
+impl UnsafeTrapEvent {
+    fn new(trigger_context: u64, result: MojoResult, state: SignalsState) -> UnsafeTrapEvent {
+        UnsafeTrapEvent {
+            trigger_context,
+            result: result as i32,
+            satisfied: state.satisfied().bits(),
+            satisfiable: state.satisfiable().bits(),
+        }
+    }
+
+    pub fn trigger_context(&self) -> u64 {
+        self.trigger_context
+    }
+
+    pub fn result(&self) -> MojoResult {
+        MojoResult::from_code(self.result)
+    }
+
+    pub fn signals_state(&self) -> SignalsState {
+        SignalsState::new(
+            HandleSignals::from_bits_truncate(self.satisfied),
+            HandleSignals::from_bits_truncate(self.satisfiable),
+        )
+    }
+}
+
+/// The outcome of [`UnsafeTrap::arm`].
+pub enum ArmResult<'a> {
+    /// The trap is now armed and watching its triggers.
+    Armed,
+    /// At least one trigger's condition already holds; the trap was not
+    /// armed. `events` holds as many of the ready events as fit in the
+    /// buffer passed to `arm`.
+    Blocked(&'a [UnsafeTrapEvent]),
+    /// Arming failed outright.
+    Failed(MojoResult),
+}
+
+/// A trap whose handler is a plain `extern "C" fn`, matching the native Mojo
+/// trap API.
+pub struct UnsafeTrap {
+    state: Arc<TrapState>,
+    handler: extern "C" fn(&UnsafeTrapEvent),
+}
+
+impl UnsafeTrap {
+    pub fn new(handler: extern "C" fn(&UnsafeTrapEvent)) -> Result<UnsafeTrap, MojoResult> {
+        Ok(UnsafeTrap { state: trap_new(), handler })
+    }
+
+    /// Registers a trigger that fires when `condition` holds for `signals`
+    /// on `handle`. `trigger_context` is handed back verbatim on
+    /// [`UnsafeTrapEvent::trigger_context`].
+    pub fn add_trigger(
+        &self,
+        handle: u32,
+        signals: HandleSignals,
+        condition: TriggerCondition,
+        trigger_context: u64,
+    ) -> MojoResult {
+        let handler = self.handler;
+        let fire = Box::new(move |result: MojoResult, state: SignalsState| {
+            handler(&UnsafeTrapEvent::new(trigger_context, result, state));
+        });
+        trap_add_trigger(&self.state, handle, signals, condition, fire).0
+    }
+
+    /// Attempts to arm the trap. If `blocking_events` is provided and one or
+    /// more triggers are already ready, their events are written there
+    /// instead of being delivered through the handler.
+    pub fn arm<'a>(
+        &self,
+        blocking_events: Option<&'a mut [MaybeUninit<UnsafeTrapEvent>]>,
+    ) -> ArmResult<'a> {
+        match blocking_events {
+            Some(buf) => {
+                // Ready events are reported directly into `buf`, not through
+                // the handler.
+                let ready = trap_arm(&self.state, /* invoke = */ false);
+                if ready.is_empty() {
+                    return ArmResult::Armed;
+                }
+                let n = ready.len().min(buf.len());
+                for (slot, event) in buf.iter_mut().zip(ready.iter()).take(n) {
+                    slot.write(UnsafeTrapEvent::new(0, event.result, event.signals_state));
+                }
+                // SAFETY: the first `n` slots were just initialized above.
+                let initialized = unsafe {
+                    std::slice::from_raw_parts(buf.as_ptr() as *const UnsafeTrapEvent, n)
+                };
+                ArmResult::Blocked(initialized)
+            }
+            None => {
+                // No buffer was supplied, so any already-ready triggers are
+                // reported through the handler instead.
+                let ready = trap_arm(&self.state, /* invoke = */ true);
+                if ready.is_empty() {
+                    ArmResult::Armed
+                } else {
+                    ArmResult::Failed(MojoResult::FailedPrecondition)
+                }
+            }
+        }
+    }
+}
+
+impl Drop for UnsafeTrap {
+    fn drop(&mut self) {
+        trap_close(&self.state);
+    }
+}
+
+/// The event delivered to a [`Trap`]'s handler.
+#[derive(Clone, Copy, Debug)]
+pub struct TrapEvent {
+    handle: u32,
+    result: MojoResult,
+    signals_state: SignalsState,
+}
+
+impl TrapEvent {
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+
+    pub fn result(&self) -> MojoResult {
+        self.result
+    }
+
+    pub fn signals_state(&self) -> SignalsState {
+        self.signals_state
+    }
+}
+
+/// An RAII handle to a single registered trigger: dropping it removes the
+/// trigger from its trap. Kept around for its `Drop` impl; it has no other
+/// observable behavior.
+pub struct TriggerToken {
+    state: Arc<TrapState>,
+    id: u64,
+}
+
+impl Drop for TriggerToken {
+    fn drop(&mut self) {
+        trap_remove_trigger(&self.state, self.id);
+    }
+}
+
+/// A trap whose handler is an arbitrary Rust closure, parameterized over a
+/// context value `C` passed to `add_trigger` and handed back to the handler.
+pub struct Trap<C> {
+    state: Arc<TrapState>,
+    handler: Arc<dyn Fn(&TrapEvent, &C) + Send + Sync>,
+}
+
+impl<C: Send + Sync + 'static> Trap<C> {
+    pub fn new<F>(handler: F) -> Result<Trap<C>, MojoResult>
+    where
+        F: Fn(&TrapEvent, &C) + Send + Sync + 'static,
+    {
+        Ok(Trap { state: trap_new(), handler: Arc::new(handler) })
+    }
+
+    pub fn add_trigger(
+        &self,
+        handle: u32,
+        signals: HandleSignals,
+        condition: TriggerCondition,
+        context: C,
+    ) -> TriggerToken {
+        let handler = self.handler.clone();
+        let context = Arc::new(context);
+        let fire = Box::new(move |result: MojoResult, state: SignalsState| {
+            let event = TrapEvent { handle, result, signals_state: state };
+            handler(&event, &context);
+        });
+        let (_, id) = trap_add_trigger(&self.state, handle, signals, condition, fire);
+        TriggerToken { state: self.state.clone(), id }
+    }
+
+    /// Attempts to arm the trap. If any trigger's condition already holds,
+    /// arming fails and the corresponding event(s) are delivered through the
+    /// handler synchronously instead.
+    pub fn arm(&self) -> MojoResult {
+        let ready = trap_arm(&self.state, /* invoke = */ true);
+        if ready.is_empty() {
+            MojoResult::Okay
+        } else {
+            MojoResult::FailedPrecondition
+        }
+    }
+}
+
+impl<C> Drop for Trap<C> {
+    fn drop(&mut self) {
+        trap_close(&self.state);
+    }
+}
@@ -0,0 +1,109 @@
+// Copyright 2024 The Chromium Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! Wait sets: a collection of handle/signals pairs that can be waited on
+//! together, reporting every member that becomes ready at once instead of
+//! requiring a separate blocking wait per handle.
+
+use std::time::Duration;
+
+use crate::system::{Handle, HandleSignals, MojoResult, SignalsState};
+
+#[cfg(feature = "emulate")]
+use crate::system::emulate;
+#[cfg(not(feature = "emulate"))]
+use crate::system::native;
+
+/// Identifies an entry previously added to a [`WaitSet`], chosen by the
+/// caller so it can be matched back up against [`WaitSetResult::cookie`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub struct WaitSetCookie(pub u64);
+
+/// One member of a [`WaitSet`] that turned out to be ready when
+/// [`WaitSet::wait_on_set`] returned.
+#[derive(Clone, Copy, Debug)]
+pub struct WaitSetResult {
+    pub cookie: WaitSetCookie,
+    pub wait_result: MojoResult,
+    pub signals_state: SignalsState,
+}
+
+struct Entry {
+    cookie: WaitSetCookie,
+    native: u32,
+    signals: HandleSignals,
+}
+
+/// A set of handle/signals pairs that can be waited on together.
+pub struct WaitSet {
+    entries: Vec<Entry>,
+}
+
+impl WaitSet {
+    pub fn new() -> Result<WaitSet, MojoResult> {
+        Ok(WaitSet { entries: Vec::new() })
+    }
+
+    /// Adds `handle` to the set, waiting on `signals`, identified by
+    /// `cookie`. Fails with `MojoResult::AlreadyExists` if `cookie` is
+    /// already in use.
+    pub fn add(&mut self, handle: &impl Handle, signals: HandleSignals, cookie: WaitSetCookie) -> MojoResult {
+        if self.entries.iter().any(|entry| entry.cookie == cookie) {
+            return MojoResult::AlreadyExists;
+        }
+        self.entries.push(Entry { cookie, native: handle.get_native_handle(), signals });
+        MojoResult::Okay
+    }
+
+    /// Removes the entry identified by `cookie`. Fails with
+    /// `MojoResult::NotFound` if there is no such entry.
+    pub fn remove(&mut self, cookie: WaitSetCookie) -> MojoResult {
+        let len_before = self.entries.len();
+        self.entries.retain(|entry| entry.cookie != cookie);
+        if self.entries.len() == len_before {
+            MojoResult::NotFound
+        } else {
+            MojoResult::Okay
+        }
+    }
+
+    /// Blocks until at least one member of the set is either satisfied or
+    /// can no longer become satisfied, appending a [`WaitSetResult`] for
+    /// every such member to `output`.
+    pub fn wait_on_set(&mut self, output: &mut Vec<WaitSetResult>) -> MojoResult {
+        output.clear();
+        loop {
+            for entry in &self.entries {
+                let state = signals(entry.native);
+                if state.satisfied().contains(entry.signals) {
+                    output.push(WaitSetResult {
+                        cookie: entry.cookie,
+                        wait_result: MojoResult::Okay,
+                        signals_state: state,
+                    });
+                } else if !state.satisfiable().contains(entry.signals) {
+                    output.push(WaitSetResult {
+                        cookie: entry.cookie,
+                        wait_result: MojoResult::FailedPrecondition,
+                        signals_state: state,
+                    });
+                }
+            }
+            if !output.is_empty() {
+                return MojoResult::Okay;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+#[cfg(feature = "emulate")]
+fn signals(native: u32) -> SignalsState {
+    emulate::signals(native)
+}
+
+#[cfg(not(feature = "emulate"))]
+fn signals(native: u32) -> SignalsState {
+    native::signals(native)
+}
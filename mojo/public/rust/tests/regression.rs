@@ -67,7 +67,7 @@ tests! {
     // Fixed size arrays have complex and unsafe semantics to ensure
     // there are no memory leaks. We test this behavior here to make
     // sure memory isn't becoming corrupted.
-    fn regression_fixed_size_array_error_propagates_safely() {
+    parallel fn regression_fixed_size_array_error_propagates_safely() {
         let handle1 = unsafe { system::acquire(0) };
         let handle2 = unsafe { system::acquire(0) };
         let handle3 = unsafe { system::acquire(0) };
@@ -88,7 +88,7 @@ tests! {
     // random number which is potentially a valid handle. When on
     // drop() we try to close it, we should panic.
     #[should_panic]
-    fn regression_fixed_size_array_verify_drop() {
+    parallel fn regression_fixed_size_array_verify_drop() {
         let handle1 = unsafe { system::acquire(42) };
         let handle2 = unsafe { system::acquire(0) };
         let handle3 = unsafe { system::acquire(0) };
@@ -24,8 +24,11 @@ use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
 use std::vec::Vec;
 
+#[macro_use]
+mod util;
+
 tests! {
-    fn handle() {
+    parallel fn handle() {
         let sb = SharedBuffer::new(1).unwrap();
         let handle = sb.as_untyped();
         unsafe {
@@ -38,7 +41,7 @@ tests! {
         }
     }
 
-    fn shared_buffer() {
+    parallel fn shared_buffer() {
         let bufsize = 100;
 
         // Create a shared buffer and test round trip through `UntypedHandle`.
@@ -78,7 +81,7 @@ tests! {
         assert_eq!(buf1.read(1), 35);
     }
 
-    fn message_pipe() {
+    parallel fn message_pipe() {
         let (end_a, end_b) = message_pipe::create().unwrap();
 
         // Extract original handle to check against.
@@ -119,7 +122,7 @@ tests! {
         assert!(s.satisfiable().is_peer_closed());
     }
 
-    fn data_pipe() {
+    parallel fn data_pipe() {
         let (consumer, producer) = data_pipe::create_default().unwrap();
         // Extract original handle to check against
         let consumer_native_handle = consumer.get_native_handle();
@@ -174,7 +177,7 @@ tests! {
         assert_eq!(data, goodbye);
     }
 
-    fn wait_set() {
+    parallel fn wait_set() {
         let mut set = wait_set::WaitSet::new().unwrap();
         let (endpt0, endpt1) = message_pipe::create().unwrap();
         let cookie1 = wait_set::WaitSetCookie(245);
@@ -199,11 +202,10 @@ tests! {
         assert!(output[0].signals_state.satisfied().is_readable());
     }
 
-    fn trap_signals_on_readable() {
-        // These tests unfortunately need global state, so we have to ensure
-        // exclusive access (generally Rust tests run on multiple threads).
-        let _test_lock = TRAP_TEST_LOCK.lock().unwrap();
-
+    serial fn trap_signals_on_readable() {
+        // This test, and `trap_handle_closed_before_arm` below, observe the
+        // process-wide `TRAP_EVENT_LIST`, so they're marked `serial` to keep
+        // them from seeing each other's events.
         let trap = UnsafeTrap::new(test_trap_event_handler).unwrap();
 
         let (cons, prod) = data_pipe::create_default().unwrap();
@@ -303,9 +305,7 @@ tests! {
         clear_trap_events(3);
     }
 
-    fn trap_handle_closed_before_arm() {
-        let _test_lock = TRAP_TEST_LOCK.lock().unwrap();
-
+    serial fn trap_handle_closed_before_arm() {
         let trap = UnsafeTrap::new(test_trap_event_handler).unwrap();
 
         let (cons, _prod) = data_pipe::create_default().unwrap();
@@ -329,7 +329,7 @@ tests! {
         clear_trap_events(1);
     }
 
-    fn safe_trap() {
+    parallel fn safe_trap() {
         struct SharedContext {
             events: Mutex<Vec<TrapEvent>>,
             cond: Condvar,
@@ -429,9 +429,9 @@ extern "C" fn test_trap_event_handler(event: &UnsafeTrapEvent) {
 }
 
 lazy_static::lazy_static! {
-    // We need globals for trap tests so we need mutual exclusion.
-    static ref TRAP_TEST_LOCK: Mutex<()> = Mutex::new(());
-    // The TrapEvents received by `test_trap_event_handler`.
+    // The TrapEvents received by `test_trap_event_handler`. `trap_signals_on_readable`
+    // and `trap_handle_closed_before_arm` are marked `serial` in the `tests!`
+    // block above so they never observe each other's events here.
     static ref TRAP_EVENT_LIST: Mutex<Vec<UnsafeTrapEvent>> = Mutex::new(Vec::new());
     static ref TRAP_EVENT_COND: Condvar = Condvar::new();
 }
@@ -0,0 +1,206 @@
+// Copyright 2024 The Chromium Authors
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! A minimal test harness used by the `mojo` Rust crates' integration tests
+//! in place of the standard `#[test]`/`cargo test` harness.
+//!
+//! Each test declares whether it's [`Mode::Serial`] (never overlaps with any
+//! other test) or [`Mode::Parallel`] (runs on a bounded worker pool
+//! alongside other parallel tests) via the [`tests!`] macro. This lets tests
+//! that share process-global state (e.g. the trap tests in `system.rs`,
+//! which all observe one process-wide trap event list) declare that
+//! directly instead of hand-rolling a lock shared by convention.
+//!
+//! Test selection and listing are controlled by the `MOJO_RUST_TEST_FILTER`
+//! (name substring) and `MOJO_RUST_TEST_LIST` environment variables, or
+//! their `--filter=`/`--list` argv equivalents.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+use std::thread;
+
+/// Whether a test may run concurrently with others.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mode {
+    /// Runs alone: no other test, serial or parallel, runs at the same time.
+    Serial,
+    /// Runs on the bounded worker pool alongside other parallel tests.
+    Parallel,
+}
+
+/// One test registered by the [`tests!`] macro.
+pub struct TestCase {
+    pub name: &'static str,
+    pub mode: Mode,
+    /// Whether `run` is expected to panic; mirrors `#[should_panic]` under
+    /// the standard `#[test]` harness. See [`tests!`].
+    pub should_panic: bool,
+    pub run: fn(),
+}
+
+/// The number of worker threads used to run `parallel` tests.
+const WORKER_COUNT: usize = 4;
+
+enum Outcome {
+    Passed,
+    Failed(String),
+    Filtered,
+}
+
+/// Declares a test binary's `main`, registering every `fn` listed, each
+/// tagged `serial` or `parallel`. A test may also be tagged
+/// `#[should_panic]`, mirroring the standard `#[test]` harness, if it's
+/// expected to panic:
+///
+/// ```ignore
+/// tests! {
+///     parallel fn handle() { /* ... */ }
+///     serial fn trap_signals_on_readable() { /* ... */ }
+///     #[should_panic]
+///     parallel fn rejects_bad_input() { /* ... */ }
+/// }
+/// ```
+#[macro_export]
+macro_rules! tests {
+    ($(
+        $(#[should_panic])?
+        $mode:ident fn $name:ident() $body:block
+    )*) => {
+        $(
+            fn $name() $body
+        )*
+
+        fn main() {
+            let cases: &[$crate::util::TestCase] = &[
+                $(
+                    $crate::util::TestCase {
+                        name: stringify!($name),
+                        mode: if stringify!($mode) == "serial" {
+                            $crate::util::Mode::Serial
+                        } else {
+                            $crate::util::Mode::Parallel
+                        },
+                        should_panic: tests!(@should_panic $(#[should_panic])?),
+                        run: $name,
+                    },
+                )*
+            ];
+            $crate::util::run(cases);
+        }
+    };
+    (@should_panic #[should_panic]) => { true };
+    (@should_panic) => { false };
+}
+
+/// Runs every test in `cases` and prints a structured summary, exiting with
+/// a nonzero status if anything failed. See the module documentation for
+/// how tests are filtered, listed, and scheduled.
+pub fn run(cases: &[TestCase]) {
+    let (filter, list_only) = parse_args();
+
+    if list_only {
+        for case in cases {
+            println!("{} ({:?})", case.name, case.mode);
+        }
+        return;
+    }
+
+    let results: Mutex<Vec<(&'static str, Outcome)>> = Mutex::new(Vec::new());
+    let (serial, parallel): (Vec<&TestCase>, Vec<&TestCase>) =
+        cases.iter().partition(|case| case.mode == Mode::Serial);
+
+    for case in &serial {
+        let outcome = run_one(case, &filter);
+        results.lock().unwrap().push((case.name, outcome));
+    }
+    run_parallel(&parallel, &filter, &results);
+
+    print_summary_and_exit(results.into_inner().unwrap());
+}
+
+fn run_parallel(cases: &[&TestCase], filter: &Option<String>, results: &Mutex<Vec<(&'static str, Outcome)>>) {
+    let next = Mutex::new(0usize);
+    let worker_count = WORKER_COUNT.min(cases.len()).max(1);
+    thread::scope(|scope| {
+        for _ in 0..worker_count {
+            scope.spawn(|| loop {
+                let index = {
+                    let mut next = next.lock().unwrap();
+                    if *next >= cases.len() {
+                        return;
+                    }
+                    let index = *next;
+                    *next += 1;
+                    index
+                };
+                let case = cases[index];
+                let outcome = run_one(case, filter);
+                results.lock().unwrap().push((case.name, outcome));
+            });
+        }
+    });
+}
+
+fn run_one(case: &TestCase, filter: &Option<String>) -> Outcome {
+    if let Some(filter) = filter {
+        if !case.name.contains(filter.as_str()) {
+            return Outcome::Filtered;
+        }
+    }
+    match (panic::catch_unwind(AssertUnwindSafe(case.run)), case.should_panic) {
+        (Ok(()), false) => Outcome::Passed,
+        (Ok(()), true) => Outcome::Failed("test did not panic".to_string()),
+        (Err(_), true) => Outcome::Passed,
+        (Err(payload), false) => Outcome::Failed(panic_message(&payload)),
+    }
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "test panicked with a non-string payload".to_string()
+    }
+}
+
+fn parse_args() -> (Option<String>, bool) {
+    let mut filter = std::env::var("MOJO_RUST_TEST_FILTER").ok();
+    let mut list_only = std::env::var("MOJO_RUST_TEST_LIST").is_ok();
+    for arg in std::env::args().skip(1) {
+        if let Some(value) = arg.strip_prefix("--filter=") {
+            filter = Some(value.to_string());
+        } else if arg == "--list" {
+            list_only = true;
+        }
+    }
+    (filter, list_only)
+}
+
+fn print_summary_and_exit(mut results: Vec<(&'static str, Outcome)>) -> ! {
+    results.sort_by_key(|(name, _)| *name);
+
+    let mut passed = 0;
+    let mut failed = 0;
+    let mut filtered = 0;
+    for (name, outcome) in &results {
+        match outcome {
+            Outcome::Passed => {
+                passed += 1;
+                println!("[ PASS ] {name}");
+            }
+            Outcome::Filtered => {
+                filtered += 1;
+                println!("[ SKIP ] {name} (filtered out)");
+            }
+            Outcome::Failed(message) => {
+                failed += 1;
+                println!("[ FAIL ] {name}: {message}");
+            }
+        }
+    }
+    println!("{passed} passed; {failed} failed; {filtered} filtered; {} total", results.len());
+    std::process::exit(if failed > 0 { 1 } else { 0 });
+}